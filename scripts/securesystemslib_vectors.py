@@ -0,0 +1,76 @@
+#!/usr/bin/env python3
+"""Writes testdata/securesystemslib/vectors.json.
+
+Needs securesystemslib installed, since the point of the vectors is to pin
+this crate to its output. The package version goes into the file.
+"""
+
+import json
+import os
+import sys
+
+try:
+    import securesystemslib
+    from securesystemslib.formats import encode_canonical
+    from securesystemslib.keys import _get_keyid
+except ImportError as err:
+    sys.exit("securesystemslib is required to generate the vectors: %s" % err)
+
+
+DOCUMENTS = [
+    None,
+    True,
+    [],
+    {},
+    -9223372036854775808,
+    18446744073709551615,
+    "quote \" backslash \\ slash /",
+    "controls \u0000 \u0008 \t \n \u000c \r \u001f \u007f",
+    "unicode é ü 日本 🎉  ",
+    {"b": 1, "a": [1, "x", None, False], "": {}, "A": -0},
+    {"é": 1, "z": 2, "\u007f": 3, "日本": 4, "🎉": 5, "￿": 6},
+    {"_type": "link", "name": "package", "command": ["tar", "zcvf", "foo.tar.gz", "foo.py"],
+     "materials": {"foo.py": {"sha256": "74dc3727c6e89308b39e4dfedf787e37841198b1fa165a27c013544a60502549"}},
+     "products": {}, "byproducts": {"return-value": 0, "stderr": "", "stdout": "a\nb\n"},
+     "environment": {}},
+]
+
+# Not valid: securesystemslib raises on floats, including integral ones.
+REJECTED = ["1.0", "1.5", "[0, 2.5e3]", '{"a": -0.0}']
+
+KEYS = [
+    ("ed25519", "ed25519", "8f2e8de5e4bdb65e1bd2cde1d11f4b5d7c1d36d0a8c0e2f43ee1ecbb3e0e1a2b"),
+    ("rsa", "rsassa-pss-sha256",
+     "-----BEGIN PUBLIC KEY-----\nMIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8AMIIBCgKCAQEA\n-----END PUBLIC KEY-----"),
+    ("ecdsa", "ecdsa-sha2-nistp256",
+     "-----BEGIN PUBLIC KEY-----\nMFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAE\n-----END PUBLIC KEY-----\n"),
+]
+
+
+def main():
+    vectors = {
+        "securesystemslib_version": securesystemslib.__version__,
+        "documents": [
+            {"input": json.dumps(doc, ensure_ascii=False), "canonical": encode_canonical(doc)}
+            for doc in DOCUMENTS
+        ],
+        "rejected": REJECTED,
+        "keys": [
+            {
+                "keytype": keytype,
+                "scheme": scheme,
+                "public": public,
+                "keyid_sha256": _get_keyid(keytype, scheme, {"public": public}, "sha256"),
+                "keyid_sha512": _get_keyid(keytype, scheme, {"public": public}, "sha512"),
+            }
+            for keytype, scheme, public in KEYS
+        ],
+    }
+    path = os.path.join(os.path.dirname(__file__), "..", "testdata", "securesystemslib", "vectors.json")
+    with open(path, "w", encoding="utf-8") as f:
+        json.dump(vectors, f, indent=2, ensure_ascii=False)
+        f.write("\n")
+
+
+if __name__ == "__main__":
+    main()
@@ -1,10 +1,226 @@
-use super::error::Error;
+use super::error::{Error, ErrorKind};
+use super::patch::{self, Patch};
+use super::ser::{FloatPolicy, Serializer};
+use super::validate::parse_pointer;
+use serde::de::{
+    self, Deserialize, DeserializeOwned, DeserializeSeed, MapAccess, SeqAccess, Visitor,
+};
 use serde::Serialize;
 use serde_json::value::*;
-use std::io::Cursor;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::hash::{Hash, Hasher};
+use std::{fmt, mem, ops, str};
 
+/// A JSON document that is known to have a canonical form: it holds no
+/// floats, and object keys are unique strings. It parses from and prints as
+/// JSON text, printing always gives the canonical bytes.
+///
+/// The canonical bytes are computed once, when the document is built, and
+/// equality, hashing and ordering work on them. Two documents are equal when
+/// they have the same canonical form, whatever order their members were
+/// built in. Documents are ordered by their canonical bytes, compared
+/// lexicographically: a total order consistent with equality, but not a
+/// numeric one (`10` sorts before `9`).
+///
+/// Nodes are addressed by JSON Pointer (RFC 6901). Edits go through `set`,
+/// `remove` and `edit`, which refuse anything that has no canonical form and
+/// leave the document as it was when they fail. Each successful edit
+/// computes the canonical bytes again.
+#[derive(Clone, Debug)]
 pub struct CanonicalValue {
     value: Value,
+    bytes: Vec<u8>,
+}
+
+impl CanonicalValue {
+    // Only called with values that passed the checks of `Checked` or
+    // `check_numbers`, so serializing can only fail on the default depth
+    // limit.
+    fn new(value: Value) -> Result<Self, Error> {
+        let bytes = super::ser::to_vec(&value)?;
+        Ok(CanonicalValue { value, bytes })
+    }
+
+    /// The canonical form of the document.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The document as a `serde_json::Value`.
+    pub fn into_inner(self) -> Value {
+        self.value
+    }
+
+    /// The document, for reading.
+    pub fn as_value(&self) -> &Value {
+        &self.value
+    }
+
+    /// The node at `pointer`, if there is one.
+    pub fn pointer(&self, pointer: &str) -> Option<&Value> {
+        self.value.pointer(pointer)
+    }
+
+    /// The node at `pointer`, deserialized as a `T`. Fails with
+    /// `ErrorKind::MissingPath` when there is no such node.
+    pub fn get<T: DeserializeOwned>(&self, pointer: &str) -> Result<T, Error> {
+        let node = self.pointer(pointer).ok_or_else(|| missing(pointer))?;
+        Ok(T::deserialize(node)?)
+    }
+
+    /// Puts `value` at `pointer` and returns the node it replaced. A missing
+    /// object member is added, and `-` appends to an array; any other node
+    /// on the way must exist. Members stay sorted and unique, and a value
+    /// with floats is refused, integral ones included.
+    pub fn set<T>(&mut self, pointer: &str, value: &T) -> Result<Option<Value>, Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        let tokens = pointer_tokens(pointer)?;
+        let mut buf = Vec::with_capacity(128);
+        let mut ser = Serializer::new(&mut buf).float_policy(FloatPolicy::Reject);
+        value
+            .serialize(&mut ser)
+            .map_err(|err| locate(err, &tokens))?;
+        let new = super::de::from_slice(&buf)?;
+
+        let last = match tokens.last() {
+            Some(last) => last,
+            None => return self.update(|value| Ok(Some(mem::replace(value, new)))),
+        };
+        let parent = &pointer[..pointer.rfind('/').unwrap_or(0)];
+        self.update(|value| match value.pointer_mut(parent) {
+            Some(Value::Object(object)) => Ok(object.insert(last.clone(), new)),
+            Some(Value::Array(array)) if last == "-" => {
+                array.push(new);
+                Ok(None)
+            }
+            Some(Value::Array(array)) => {
+                let i = patch::array_index(last, array.len()).ok_or_else(|| missing(pointer))?;
+                Ok(Some(mem::replace(&mut array[i], new)))
+            }
+            _ => Err(missing(pointer)),
+        })
+    }
+
+    /// Takes the node at `pointer` out of the document. Elements after a
+    /// removed array element move down.
+    pub fn remove(&mut self, pointer: &str) -> Result<Value, Error> {
+        self.update(|value| patch::remove(value, pointer))
+    }
+
+    /// Edits the node at `pointer` in place. If `f` leaves a float behind,
+    /// the node is put back as it was and the edit fails.
+    pub fn edit<F, R>(&mut self, pointer: &str, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut Value) -> R,
+    {
+        let tokens = pointer_tokens(pointer)?;
+        self.update(|value| {
+            let node = value.pointer_mut(pointer).ok_or_else(|| missing(pointer))?;
+            let result = f(node);
+            check_numbers(node).map_err(|err| locate(err, &tokens))?;
+            Ok(result)
+        })
+    }
+
+    /// Applies a JSON Patch (RFC 6902). Either every operation applies and
+    /// the result is canonical, or the document is left as it was.
+    pub fn apply_patch(&mut self, patch: &Patch) -> Result<(), Error> {
+        self.update(|value| {
+            patch::apply(value, patch)?;
+            check_numbers(value)
+        })
+    }
+
+    /// A JSON Patch that turns this document into `other`.
+    ///
+    /// Members are visited in key order and arrays from the front, so the
+    /// same two documents always give the same patch. Objects are compared
+    /// member by member. Arrays are aligned by edit distance, so that the
+    /// fewest elements are changed, removed or added; a shifted array takes
+    /// one `add` and one `remove`, not a change to every element. Changed
+    /// elements and members are compared in turn, down to the scalars that
+    /// differ, which are replaced, as are nodes whose type changed. This
+    /// says precisely where the documents differ, but a patch replacing a
+    /// whole object can be shorter. The patch never uses `move`, `copy` or
+    /// `test`. Paths are where each node is once the operations before it
+    /// have been applied.
+    ///
+    /// Very long arrays that differ in many places are compared position by
+    /// position instead of aligned, to bound the memory a diff needs.
+    pub fn diff(&self, other: &CanonicalValue) -> Patch {
+        patch::diff(&self.value, &other.value)
+    }
+
+    // Makes a change to a copy of the document, and keeps the copy only if
+    // `f` succeeds and the result still serializes. Serializing can fail on
+    // the depth limit even when every node is fine on its own.
+    fn update<F, R>(&mut self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut Value) -> Result<R, Error>,
+    {
+        let mut value = self.value.clone();
+        let result = f(&mut value)?;
+        self.bytes = super::ser::to_vec(&value)?;
+        self.value = value;
+        Ok(result)
+    }
+}
+
+pub(crate) fn pointer_tokens(pointer: &str) -> Result<Vec<String>, Error> {
+    parse_pointer(pointer).ok_or_else(|| Error::canonical(ErrorKind::InvalidPointer, pointer))
+}
+
+pub(crate) fn missing(pointer: &str) -> Error {
+    let err = Error::canonical(ErrorKind::MissingPath, pointer);
+    match parse_pointer(pointer) {
+        Some(tokens) => locate(err, &tokens),
+        None => err,
+    }
+}
+
+// Moves an error found in a node down to where the node is in the document.
+pub(crate) fn locate(err: Error, tokens: &[String]) -> Error {
+    tokens.iter().rev().fold(err, |err, token| err.at(token))
+}
+
+/// Looks up a member by key or an element by position, giving `Null` when
+/// there is none, like `serde_json::Value`.
+impl<I: Index> ops::Index<I> for CanonicalValue {
+    type Output = Value;
+
+    fn index(&self, index: I) -> &Value {
+        &self.value[index]
+    }
+}
+
+impl PartialEq for CanonicalValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.bytes == other.bytes
+    }
+}
+
+impl Eq for CanonicalValue {}
+
+impl Hash for CanonicalValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.bytes.hash(state);
+    }
+}
+
+impl PartialOrd for CanonicalValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for CanonicalValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.bytes.cmp(&other.bytes)
+    }
 }
 
 impl serde::Serialize for CanonicalValue {
@@ -32,22 +248,244 @@ impl serde::Serialize for CanonicalValue {
     }
 }
 
-pub fn to_value<T>(value: T) -> Result<CanonicalValue, Error>
+// A value built while it is deserialized, refusing every float, integers
+// `Value` cannot hold and repeated keys. The canonical bytes are left to the
+// `CanonicalValue` at the top.
+//
+// Deserializers only carry a message, so the visitor also leaves the error
+// it stopped on in `slot`. Callers that know the deserializer hand that back
+// with its `ErrorKind` and the pointer to the node, see `checked`.
+#[derive(Clone, Copy)]
+struct Checked<'a> {
+    slot: &'a RefCell<Option<Error>>,
+}
+
+impl<'a> Checked<'a> {
+    fn reject<E: de::Error>(self, kind: ErrorKind, value: impl fmt::Display) -> E {
+        let err = Error::canonical(kind, value);
+        let message = E::custom(err.detail());
+        *self.slot.borrow_mut() = Some(err);
+        message
+    }
+
+    // Moves the error left by a child down to where the child is.
+    fn locate<E>(self, err: E, token: impl fmt::Display) -> E {
+        let mut slot = self.slot.borrow_mut();
+        if let Some(inner) = slot.take() {
+            *slot = Some(inner.at(token));
+        }
+        err
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for Checked<'_> {
+    type Value = Value;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Value, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Checked<'_> {
+    type Value = Value;
+
+    fn expecting(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("a JSON value without floating point numbers")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<Value, E> {
+        Ok(Value::Bool(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Value, E> {
+        Ok(v.into())
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Value, E> {
+        Ok(v.into())
+    }
+
+    fn visit_i128<E: de::Error>(self, v: i128) -> Result<Value, E> {
+        match (i64::try_from(v), u64::try_from(v)) {
+            (Ok(v), _) => Ok(v.into()),
+            (_, Ok(v)) => Ok(v.into()),
+            _ => Err(self.reject(ErrorKind::NumberOutOfRange, v)),
+        }
+    }
+
+    fn visit_u128<E: de::Error>(self, v: u128) -> Result<Value, E> {
+        match u64::try_from(v) {
+            Ok(v) => Ok(v.into()),
+            Err(_) => Err(self.reject(ErrorKind::NumberOutOfRange, v)),
+        }
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Value, E> {
+        let kind = if v.is_finite() {
+            ErrorKind::Float
+        } else {
+            ErrorKind::NonFinite
+        };
+        Err(self.reject(kind, v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Value, E> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<Value, E> {
+        Ok(Value::String(v))
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_none<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> Result<Value, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        self.deserialize(deserializer)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut array = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(element) = seq
+            .next_element_seed(self)
+            .map_err(|err| self.locate(err, array.len()))?
+        {
+            array.push(element);
+        }
+        Ok(Value::Array(array))
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut object = Map::new();
+        while let Some(key) = map.next_key::<String>()? {
+            let value = map
+                .next_value_seed(self)
+                .map_err(|err| self.locate(err, &key))?;
+            if object.contains_key(&key) {
+                return Err(self.reject(ErrorKind::DuplicateKey, &key));
+            }
+            object.insert(key, value);
+        }
+        Ok(Value::Object(object))
+    }
+}
+
+// Deserializes a document, giving back the error the visitor stopped on
+// rather than the deserializer's message about it.
+fn checked<'de, D>(deserializer: D) -> Result<Value, Error>
 where
-    T: Serialize,
+    D: de::Deserializer<'de>,
+    Error: From<D::Error>,
 {
-    ensure_canonical(&value)?;
-    let val = serde_json::to_value(value)?;
-    Ok(CanonicalValue { value: val })
+    let slot = RefCell::new(None);
+    Checked { slot: &slot }
+        .deserialize(deserializer)
+        .map_err(|err| slot.into_inner().unwrap_or_else(|| err.into()))
 }
 
-fn ensure_canonical<T>(value: &T) -> Result<(), Error>
+impl<'de> Deserialize<'de> for CanonicalValue {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let slot = RefCell::new(None);
+        let value = Checked { slot: &slot }.deserialize(deserializer)?;
+        CanonicalValue::new(value).map_err(|err| de::Error::custom(err.detail()))
+    }
+}
+
+/// Parses JSON text, which need not be canonical itself.
+impl str::FromStr for CanonicalValue {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let mut de = serde_json::Deserializer::from_str(s);
+        let value = checked(&mut de)?;
+        de.end()?;
+        CanonicalValue::new(value)
+    }
+}
+
+/// Writes the canonical form of the document.
+impl fmt::Display for CanonicalValue {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // canonical output is always UTF-8
+        fmt.write_str(str::from_utf8(&self.bytes).map_err(|_| fmt::Error)?)
+    }
+}
+
+impl TryFrom<Value> for CanonicalValue {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<Self, Error> {
+        check_numbers(&value)?;
+        CanonicalValue::new(value)
+    }
+}
+
+// `Value` cannot hold non-finite numbers or repeated keys, so floats are all
+// that is left to refuse.
+fn check_numbers(value: &Value) -> Result<(), Error> {
+    match *value {
+        Value::Number(ref n) if n.is_f64() => Err(Error::canonical(ErrorKind::Float, n)),
+        Value::Array(ref v) => v
+            .iter()
+            .enumerate()
+            .try_for_each(|(i, v)| check_numbers(v).map_err(|err| err.at(i))),
+        Value::Object(ref m) => m
+            .iter()
+            .try_for_each(|(k, v)| check_numbers(v).map_err(|err| err.at(k))),
+        _ => Ok(()),
+    }
+}
+
+/// Merges the JSON Merge Patch (RFC 7386) `patch` into `target`: members
+/// of an object patch set those of `target`, or delete them when `null`, and
+/// a patch that is not an object replaces `target` outright. The result of
+/// merging two canonical documents is always canonical.
+pub fn merge_patch(target: &mut CanonicalValue, patch: &CanonicalValue) -> Result<(), Error> {
+    target.update(|value| {
+        patch::merge(value, &patch.value);
+        Ok(())
+    })
+}
+
+/// The smallest merge patch that turns `old` into `new`: only the members
+/// that differ, recursively. A document that is not an object replaces
+/// `old` whole. Fails with `ErrorKind::MergePatchNull` when `new` adds a
+/// `null` member, which a merge patch cannot express.
+pub fn merge_patch_diff(
+    old: &CanonicalValue,
+    new: &CanonicalValue,
+) -> Result<CanonicalValue, Error> {
+    CanonicalValue::new(patch::merge_diff(&old.value, &new.value)?)
+}
+
+pub fn to_value<T>(value: T) -> Result<CanonicalValue, Error>
 where
-    T: serde::Serialize,
-    T: ?Sized,
+    T: Serialize,
 {
-    let c = Cursor::new(Vec::new());
-    let mut ser = super::ser::Serializer::new(c);
-    value.serialize(&mut ser)?;
-    Ok(())
+    // going through the canonical form turns integral floats into integers
+    let bytes = super::ser::to_vec(&value)?;
+    let mut de = super::de::Deserializer::from_slice(&bytes);
+    let value = checked(&mut de)?;
+    de.end()?;
+    CanonicalValue::new(value)
 }
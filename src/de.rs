@@ -0,0 +1,534 @@
+//! Strict parser that only accepts documents that are already in canonical
+//! form, byte for byte.
+//!
+//! Anything the serializer would not have produced is rejected: whitespace
+//! between tokens, object keys that are not in strictly ascending order,
+//! floating point numbers, integers with leading zeros, and any escape other
+//! than `\"` and `\\`. Errors are reported as `Error::Parse` with the byte
+//! offset at which the rule was broken.
+
+use super::error::{Error, ErrorKind, Result};
+use super::limits::Limits;
+use serde::de::{self, value::BorrowedStrDeserializer, value::StrDeserializer, DeserializeOwned};
+use std::{borrow::Cow, cmp::Ordering, io, str};
+
+/// Parses `v` with the default `Limits`, which only cap the depth.
+pub fn from_slice<'a, T>(v: &'a [u8]) -> Result<T>
+where
+    T: de::Deserialize<'a>,
+{
+    from_slice_with_limits(v, Limits::default())
+}
+
+pub fn from_str<'a, T>(s: &'a str) -> Result<T>
+where
+    T: de::Deserialize<'a>,
+{
+    from_slice(s.as_bytes())
+}
+
+pub fn from_reader<R, T>(reader: R) -> Result<T>
+where
+    R: io::Read,
+    T: DeserializeOwned,
+{
+    from_reader_with_limits(reader, Limits::default())
+}
+
+/// `from_slice` for untrusted input: rejects documents that go over one of
+/// `limits`.
+pub fn from_slice_with_limits<'a, T>(v: &'a [u8], limits: Limits) -> Result<T>
+where
+    T: de::Deserialize<'a>,
+{
+    let mut de = Deserializer::from_slice(v).limits(limits);
+    let value = T::deserialize(&mut de)?;
+    de.end()?;
+    Ok(value)
+}
+
+pub fn from_str_with_limits<'a, T>(s: &'a str, limits: Limits) -> Result<T>
+where
+    T: de::Deserialize<'a>,
+{
+    from_slice_with_limits(s.as_bytes(), limits)
+}
+
+pub fn from_reader_with_limits<R, T>(mut reader: R, limits: Limits) -> Result<T>
+where
+    R: io::Read,
+    T: DeserializeOwned,
+{
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf)?;
+    from_slice_with_limits(&buf, limits)
+}
+
+pub struct Deserializer<'de> {
+    input: &'de [u8],
+    index: usize,
+    limits: Limits,
+    // arrays and objects currently open
+    depth: usize,
+}
+
+impl<'de> Deserializer<'de> {
+    pub fn from_slice(input: &'de [u8]) -> Self {
+        Deserializer {
+            input,
+            index: 0,
+            limits: Limits::default(),
+            depth: 0,
+        }
+    }
+
+    /// Rejects input that goes over one of `limits`, other than the output
+    /// size. The default only caps the depth.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Checks that the whole input has been consumed. Call this after
+    /// deserializing a value to reject trailing data.
+    pub fn end(&mut self) -> Result<()> {
+        match self.peek() {
+            None => Ok(()),
+            Some(b) if is_whitespace(b) => Err(self.error(ErrorKind::Whitespace)),
+            Some(_) => Err(self.error(ErrorKind::TrailingCharacters)),
+        }
+    }
+
+    fn error(&self, kind: ErrorKind) -> Error {
+        Error::Parse {
+            kind,
+            offset: self.index,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.index).cloned()
+    }
+
+    // Consumes the `[` or `{` that opens a container; `leave` is called once
+    // it is closed.
+    fn enter(&mut self) -> Result<()> {
+        self.depth += 1;
+        match self.limits.max_depth {
+            Some(max) if self.depth > max => Err(self.error(ErrorKind::TooDeep)),
+            _ => {
+                self.index += 1;
+                Ok(())
+            }
+        }
+    }
+
+    fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    // Next byte at a position where a JSON token starts. Plain JSON would
+    // allow whitespace here, which is the one place canonical JSON differs.
+    fn peek_token(&self) -> Result<u8> {
+        match self.peek() {
+            None => Err(self.error(ErrorKind::UnexpectedEof)),
+            Some(b) if is_whitespace(b) => Err(self.error(ErrorKind::Whitespace)),
+            Some(b) => Ok(b),
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<()> {
+        if self.peek_token()? != byte {
+            return Err(self.error(ErrorKind::UnexpectedByte));
+        }
+        self.index += 1;
+        Ok(())
+    }
+
+    fn parse_ident(&mut self, ident: &[u8]) -> Result<()> {
+        for &expected in ident {
+            match self.peek() {
+                None => return Err(self.error(ErrorKind::UnexpectedEof)),
+                Some(b) if b == expected => self.index += 1,
+                Some(_) => return Err(self.error(ErrorKind::UnexpectedByte)),
+            }
+        }
+        Ok(())
+    }
+
+    // Parses the rest of a string whose opening quote has been consumed.
+    // Strings without escapes are borrowed from the input.
+    fn parse_str(&mut self) -> Result<Cow<'de, str>> {
+        let start = self.index;
+        let mut scratch = Vec::new();
+        let mut run = start;
+
+        loop {
+            match self.peek() {
+                None => return Err(self.error(ErrorKind::UnexpectedEof)),
+                Some(b'"') => break,
+                Some(b'\\') => match self.input.get(self.index + 1) {
+                    Some(&b) if b == b'"' || b == b'\\' => {
+                        scratch.extend_from_slice(&self.input[run..self.index]);
+                        scratch.push(b);
+                        self.index += 2;
+                        run = self.index;
+                    }
+                    Some(b'/') | Some(b'b') | Some(b'f') | Some(b'n') | Some(b'r') | Some(b't')
+                    | Some(b'u') => return Err(self.error(ErrorKind::NonMinimalEscape)),
+                    None => {
+                        return Err(Error::Parse {
+                            kind: ErrorKind::UnexpectedEof,
+                            offset: self.input.len(),
+                        })
+                    }
+                    Some(_) => return Err(self.error(ErrorKind::InvalidEscape)),
+                },
+                Some(_) => self.index += 1,
+            }
+        }
+
+        let end = self.index;
+        if let Some(max) = self.limits.max_string_len {
+            // the unescaped length
+            if scratch.len() + (end - run) > max {
+                return Err(Error::Parse {
+                    kind: ErrorKind::StringTooLong,
+                    offset: start - 1,
+                });
+            }
+        }
+        self.index += 1;
+
+        if let Err(err) = str::from_utf8(&self.input[start..end]) {
+            return Err(Error::Parse {
+                kind: ErrorKind::InvalidUtf8,
+                offset: start + err.valid_up_to(),
+            });
+        }
+
+        if scratch.is_empty() && run == start {
+            // validated above
+            Ok(Cow::Borrowed(unsafe {
+                str::from_utf8_unchecked(&self.input[start..end])
+            }))
+        } else {
+            scratch.extend_from_slice(&self.input[run..end]);
+            // escapes only ever replace ASCII with ASCII
+            Ok(Cow::Owned(unsafe { String::from_utf8_unchecked(scratch) }))
+        }
+    }
+
+    fn parse_integer<V>(&mut self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        let start = self.index;
+        let negative = self.peek() == Some(b'-');
+        if negative {
+            self.index += 1;
+        }
+
+        match self.peek() {
+            Some(b'0') => {
+                self.index += 1;
+                if let Some(b'0'..=b'9') = self.peek() {
+                    return Err(Error::Parse {
+                        kind: ErrorKind::LeadingZero,
+                        offset: start,
+                    });
+                }
+                if negative {
+                    return Err(Error::Parse {
+                        kind: ErrorKind::NegativeZero,
+                        offset: start,
+                    });
+                }
+            }
+            Some(b'1'..=b'9') => {
+                while let Some(b'0'..=b'9') = self.peek() {
+                    self.index += 1;
+                }
+            }
+            None => return Err(self.error(ErrorKind::UnexpectedEof)),
+            Some(_) => return Err(self.error(ErrorKind::UnexpectedByte)),
+        }
+
+        if let Some(b'.') | Some(b'e') | Some(b'E') = self.peek() {
+            return Err(Error::Parse {
+                kind: ErrorKind::Float,
+                offset: start,
+            });
+        }
+
+        // only ASCII digits and an optional sign were consumed
+        let text = unsafe { str::from_utf8_unchecked(&self.input[start..self.index]) };
+        let out_of_range = Error::Parse {
+            kind: ErrorKind::NumberOutOfRange,
+            offset: start,
+        };
+        // 64-bit visitors are preferred since every integer visitor supports
+        // them; only wider values go to the 128-bit ones
+        if negative {
+            match text.parse() {
+                Ok(v) => visitor.visit_i64(v),
+                Err(_) => visitor.visit_i128(text.parse().map_err(|_| out_of_range)?),
+            }
+        } else {
+            match text.parse() {
+                Ok(v) => visitor.visit_u64(v),
+                Err(_) => visitor.visit_u128(text.parse().map_err(|_| out_of_range)?),
+            }
+        }
+    }
+}
+
+fn is_whitespace(b: u8) -> bool {
+    b == b' ' || b == b'\t' || b == b'\n' || b == b'\r'
+}
+
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        match self.peek_token()? {
+            b'n' => {
+                self.parse_ident(b"null")?;
+                visitor.visit_unit()
+            }
+            b't' => {
+                self.parse_ident(b"true")?;
+                visitor.visit_bool(true)
+            }
+            b'f' => {
+                self.parse_ident(b"false")?;
+                visitor.visit_bool(false)
+            }
+            b'"' => {
+                self.index += 1;
+                match self.parse_str()? {
+                    Cow::Borrowed(s) => visitor.visit_borrowed_str(s),
+                    Cow::Owned(s) => visitor.visit_string(s),
+                }
+            }
+            b'[' => {
+                self.enter()?;
+                let value = visitor.visit_seq(SeqAccess {
+                    de: &mut *self,
+                    first: true,
+                })?;
+                self.expect(b']')?;
+                self.leave();
+                Ok(value)
+            }
+            b'{' => {
+                self.enter()?;
+                let value = visitor.visit_map(MapAccess {
+                    de: &mut *self,
+                    prev_key: None,
+                    len: 0,
+                })?;
+                self.expect(b'}')?;
+                self.leave();
+                Ok(value)
+            }
+            b'-' | b'0'..=b'9' => self.parse_integer(visitor),
+            _ => Err(self.error(ErrorKind::UnexpectedByte)),
+        }
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        if self.peek_token()? == b'n' {
+            self.parse_ident(b"null")?;
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    // Enums are expected in the externally tagged representation the
+    // serializer produces: `"Variant"` or `{"Variant":value}`.
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        match self.peek_token()? {
+            b'"' => {
+                self.index += 1;
+                match self.parse_str()? {
+                    Cow::Borrowed(s) => {
+                        visitor.visit_enum(BorrowedStrDeserializer::<Error>::new(s))
+                    }
+                    Cow::Owned(s) => visitor.visit_enum(StrDeserializer::<Error>::new(&s)),
+                }
+            }
+            b'{' => {
+                self.enter()?;
+                let value = visitor.visit_enum(VariantAccess { de: &mut *self })?;
+                self.expect(b'}')?;
+                self.leave();
+                Ok(value)
+            }
+            _ => Err(self.error(ErrorKind::UnexpectedByte)),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes
+        byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+struct SeqAccess<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+    first: bool,
+}
+
+impl<'de, 'a> de::SeqAccess<'de> for SeqAccess<'a, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: de::DeserializeSeed<'de>,
+    {
+        if self.de.peek_token()? == b']' {
+            return Ok(None);
+        }
+        if !self.first {
+            self.de.expect(b',')?;
+        }
+        self.first = false;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+}
+
+struct MapAccess<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+    prev_key: Option<Cow<'de, str>>,
+    // members read so far
+    len: usize,
+}
+
+impl<'de, 'a> de::MapAccess<'de> for MapAccess<'a, 'de> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+    where
+        K: de::DeserializeSeed<'de>,
+    {
+        if self.de.peek_token()? == b'}' {
+            return Ok(None);
+        }
+        if self.prev_key.is_some() {
+            self.de.expect(b',')?;
+        }
+
+        let offset = self.de.index;
+        if let Some(max) = self.de.limits.max_keys {
+            if self.len >= max {
+                return Err(Error::Parse {
+                    kind: ErrorKind::TooManyKeys,
+                    offset,
+                });
+            }
+        }
+        self.len += 1;
+        self.de.expect(b'"')?;
+        let key = self.de.parse_str()?;
+        if let Some(ref prev) = self.prev_key {
+            let kind = match key.as_bytes().cmp(prev.as_bytes()) {
+                Ordering::Greater => None,
+                Ordering::Equal => Some(ErrorKind::DuplicateKey),
+                Ordering::Less => Some(ErrorKind::UnorderedKey),
+            };
+            if let Some(kind) = kind {
+                return Err(Error::Parse { kind, offset });
+            }
+        }
+
+        let value = match key {
+            Cow::Borrowed(s) => seed.deserialize(BorrowedStrDeserializer::<Error>::new(s))?,
+            Cow::Owned(ref s) => seed.deserialize(StrDeserializer::<Error>::new(s))?,
+        };
+        self.prev_key = Some(key);
+        Ok(Some(value))
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+    where
+        V: de::DeserializeSeed<'de>,
+    {
+        self.de.expect(b':')?;
+        seed.deserialize(&mut *self.de)
+    }
+}
+
+struct VariantAccess<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+}
+
+impl<'de, 'a> de::EnumAccess<'de> for VariantAccess<'a, 'de> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self)>
+    where
+        V: de::DeserializeSeed<'de>,
+    {
+        self.de.expect(b'"')?;
+        let value = match self.de.parse_str()? {
+            Cow::Borrowed(s) => seed.deserialize(BorrowedStrDeserializer::<Error>::new(s))?,
+            Cow::Owned(s) => seed.deserialize(StrDeserializer::<Error>::new(&s))?,
+        };
+        self.de.expect(b':')?;
+        Ok((value, self))
+    }
+}
+
+impl<'de, 'a> de::VariantAccess<'de> for VariantAccess<'a, 'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        de::Deserialize::deserialize(self.de)
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
+    where
+        T: de::DeserializeSeed<'de>,
+    {
+        seed.deserialize(self.de)
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        de::Deserializer::deserialize_seq(self.de, visitor)
+    }
+
+    fn struct_variant<V>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        de::Deserializer::deserialize_map(self.de, visitor)
+    }
+}
@@ -0,0 +1,175 @@
+//! Finding where two documents differ once both are canonical.
+//!
+//! This answers the question behind most failed signature checks: what
+//! differs between the bytes that were signed and the bytes we produced?
+//! `diff` lists the JSON Pointers that were added, removed or changed, with
+//! their values, and the offset of the first byte at which the canonical
+//! forms differ. Two documents are equal when their canonical forms are.
+
+use super::canonical_value::{to_value, CanonicalValue};
+use super::error::Result;
+use super::patch::{self, Operation};
+use super::ser;
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+
+/// How two documents differ.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Diff {
+    /// The differences, in the order of `CanonicalValue::diff`. Each
+    /// pointer is where the node is once the changes before it are made.
+    pub changes: Vec<Change>,
+    /// Where the canonical forms first differ, or their common length when
+    /// one is a prefix of the other. `None` when the documents are equal.
+    pub first_difference: Option<usize>,
+}
+
+/// One difference between two documents.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Change {
+    /// A node only the second document has.
+    Added { pointer: String, value: Value },
+    /// A node only the first document has.
+    Removed { pointer: String, value: Value },
+    /// A node that has another value, or another type, in the second
+    /// document.
+    Changed {
+        pointer: String,
+        before: Value,
+        after: Value,
+    },
+}
+
+impl Diff {
+    /// Whether the canonical forms are the same.
+    pub fn is_equal(&self) -> bool {
+        self.first_difference.is_none()
+    }
+}
+
+/// Canonicalizes `a` and `b` and compares them.
+pub fn diff<A, B>(a: &A, b: &B) -> Result<Diff>
+where
+    A: Serialize + ?Sized,
+    B: Serialize + ?Sized,
+{
+    Ok(diff_values(&to_value(a)?, &to_value(b)?))
+}
+
+/// Compares two canonical documents.
+pub fn diff_values(a: &CanonicalValue, b: &CanonicalValue) -> Diff {
+    let first_difference = first_difference(a.as_bytes(), b.as_bytes());
+    // each path is where the node is once the operations before it have been
+    // applied, so the value it replaces or removes is looked up in a copy of
+    // `a` that follows along
+    let mut current = a.as_value().clone();
+    let mut changes = Vec::new();
+    for operation in a.diff(b).0 {
+        let before = |path: &str| current.pointer(path).cloned().unwrap_or(Value::Null);
+        let change = match operation {
+            Operation::Add {
+                ref path,
+                ref value,
+            } => Change::Added {
+                pointer: path.clone(),
+                value: value.clone(),
+            },
+            Operation::Remove { ref path } => Change::Removed {
+                pointer: path.clone(),
+                value: before(path),
+            },
+            Operation::Replace {
+                ref path,
+                ref value,
+            } => Change::Changed {
+                pointer: path.clone(),
+                before: before(path),
+                after: value.clone(),
+            },
+            Operation::Move { .. } | Operation::Copy { .. } | Operation::Test { .. } => {
+                unreachable!("diff makes only add, remove and replace operations")
+            }
+        };
+        patch::apply_operation(&mut current, &operation)
+            .expect("a diff applies to the document it was made from");
+        changes.push(change);
+    }
+    Diff {
+        changes,
+        first_difference,
+    }
+}
+
+/// Whether `a` and `b` have the same canonical form.
+pub fn equal<A, B>(a: &A, b: &B) -> Result<bool>
+where
+    A: Serialize + ?Sized,
+    B: Serialize + ?Sized,
+{
+    Ok(ser::to_vec(a)? == ser::to_vec(b)?)
+}
+
+fn first_difference(a: &[u8], b: &[u8]) -> Option<usize> {
+    match a.iter().zip(b).position(|(a, b)| a != b) {
+        Some(offset) => Some(offset),
+        None if a.len() == b.len() => None,
+        None => Some(a.len().min(b.len())),
+    }
+}
+
+// Values are shown in their canonical form, and the root, whose pointer is
+// empty, as `(root)`.
+struct Shown<'a>(&'a Value);
+
+impl fmt::Display for Shown<'_> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = ser::to_string(self.0).map_err(|_| fmt::Error)?;
+        fmt.write_str(&text)
+    }
+}
+
+fn shown_pointer(pointer: &str) -> &str {
+    if pointer.is_empty() {
+        "(root)"
+    } else {
+        pointer
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added { pointer, value } => {
+                write!(fmt, "+ {}: {}", shown_pointer(pointer), Shown(value))
+            }
+            Change::Removed { pointer, value } => {
+                write!(fmt, "- {}: {}", shown_pointer(pointer), Shown(value))
+            }
+            Change::Changed {
+                pointer,
+                before,
+                after,
+            } => write!(
+                fmt,
+                "~ {}: {} -> {}",
+                shown_pointer(pointer),
+                Shown(before),
+                Shown(after)
+            ),
+        }
+    }
+}
+
+/// One line per change, then where the canonical forms first differ.
+impl fmt::Display for Diff {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in &self.changes {
+            writeln!(fmt, "{}", change)?;
+        }
+        match self.first_difference {
+            Some(offset) => write!(fmt, "canonical forms first differ at byte {}", offset),
+            None => write!(fmt, "canonical forms are equal"),
+        }
+    }
+}
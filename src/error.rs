@@ -1,27 +1,254 @@
+use super::validate::escape_token;
 use std::{error, fmt, io, result};
 
 #[derive(Debug)]
 pub enum Error {
     Custom(String),
     Io(io::Error),
+    /// Input rejected by the canonical parser, with the byte offset at which
+    /// the broken rule was detected.
+    Parse {
+        kind: ErrorKind,
+        offset: usize,
+    },
+    /// A signature check that failed.
+    Verify(ErrorKind),
+    /// Value the serializer cannot represent in canonical form, with the
+    /// JSON Pointer (RFC 6901) of the node it was found at.
+    Canonical {
+        kind: ErrorKind,
+        value: String,
+        pointer: String,
+    },
+}
+
+impl Error {
+    pub(crate) fn canonical<T: fmt::Display>(kind: ErrorKind, value: T) -> Self {
+        Error::Canonical {
+            kind,
+            value: value.to_string(),
+            pointer: String::new(),
+        }
+    }
+
+    // Prepends a reference token to the pointer of a serializer error on its
+    // way up from the node that caused it. Nothing is tracked on the way
+    // down, so documents that serialize fine pay nothing for it.
+    pub(crate) fn at<T: fmt::Display>(mut self, token: T) -> Self {
+        if let Error::Canonical {
+            ref mut pointer, ..
+        } = self
+        {
+            *pointer = format!("/{}{}", escape_token(&token.to_string()), pointer);
+        }
+        self
+    }
+
+    /// The rule that was broken, or `None` for custom errors raised by
+    /// `Serialize` and `Deserialize` implementations.
+    pub fn kind(&self) -> Option<ErrorKind> {
+        match *self {
+            Error::Custom(..) => None,
+            Error::Io(..) => Some(ErrorKind::Io),
+            Error::Verify(kind) | Error::Parse { kind, .. } | Error::Canonical { kind, .. } => {
+                Some(kind)
+            }
+        }
+    }
+
+    /// JSON Pointer of the node a serializer error was found at. The empty
+    /// string is the whole document.
+    pub fn pointer(&self) -> Option<&str> {
+        match *self {
+            Error::Canonical { ref pointer, .. } => Some(pointer),
+            _ => None,
+        }
+    }
+}
+
+/// The rule a document broke.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ErrorKind {
+    /// The input ended in the middle of a value.
+    UnexpectedEof,
+    /// A byte that cannot start or continue the value being parsed.
+    UnexpectedByte,
+    /// Data after the end of the top-level value.
+    TrailingCharacters,
+    /// A string that is not valid UTF-8.
+    InvalidUtf8,
+    /// Whitespace between tokens.
+    Whitespace,
+    /// An object key that sorts before the key preceding it.
+    UnorderedKey,
+    /// An object key equal to the key preceding it.
+    DuplicateKey,
+    /// A number with a fraction or an exponent, or a float the serializer's
+    /// `FloatPolicy` does not allow.
+    Float,
+    /// A NaN or infinite float, which JSON cannot represent.
+    NonFinite,
+    /// An integer with a leading zero.
+    LeadingZero,
+    /// The integer `-0`.
+    NegativeZero,
+    /// An integer outside the range the parser or the scheme supports.
+    NumberOutOfRange,
+    /// A map key the serializer cannot write as a string.
+    NonStringKey,
+    /// A valid JSON escape other than `\"` and `\\`.
+    NonMinimalEscape,
+    /// A backslash followed by something JSON does not define as an escape.
+    InvalidEscape,
+    /// Arrays and objects nested deeper than `Limits::max_depth`.
+    TooDeep,
+    /// Output larger than `Limits::max_output_bytes`.
+    OutputTooLarge,
+    /// A string or key longer than `Limits::max_string_len`.
+    StringTooLong,
+    /// An object with more members than `Limits::max_keys`.
+    TooManyKeys,
+    /// The underlying reader or writer failed.
+    Io,
+    /// A content digest that is not an algorithm name, a colon and the
+    /// lowercase hex of a hash of the right length.
+    InvalidDigest,
+    /// Fewer valid signatures by trusted keys than the threshold requires.
+    SignatureThreshold,
+    /// A JWS that is malformed or uses a header this crate does not support.
+    InvalidJws,
+    /// A signature that does not verify with the given key.
+    BadSignature,
+    /// A JWK of an unknown key type or without one of the members its key
+    /// type requires.
+    InvalidJwk,
+    /// A value that had to be a JSON object.
+    NotAnObject,
+    /// A Matrix event whose content does not match its content hash.
+    ContentHashMismatch,
+    /// A string that is not a JSON Pointer (RFC 6901).
+    InvalidPointer,
+    /// A JSON Pointer, such as a required projection path or the target of
+    /// an edit, that matches nothing in the value.
+    MissingPath,
+    /// A MAC that does not match the value.
+    InvalidMac,
+    /// A key of a length the MAC does not accept.
+    InvalidKey,
+    /// A token that is not two base64url parts separated by a dot.
+    InvalidToken,
+    /// A JSON Patch `test` operation whose value differs from the document.
+    TestFailed,
+    /// A JSON Patch operation that cannot be applied as written, such as a
+    /// `move` into a child of the node it moves.
+    InvalidPatch,
+    /// A `null` member that a JSON Merge Patch would have to add, which it
+    /// cannot since `null` deletes.
+    MergePatchNull,
+}
+
+impl ErrorKind {
+    fn description(self) -> &'static str {
+        match self {
+            ErrorKind::UnexpectedEof => "unexpected end of input",
+            ErrorKind::UnexpectedByte => "unexpected byte",
+            ErrorKind::TrailingCharacters => "trailing characters",
+            ErrorKind::InvalidUtf8 => "invalid UTF-8",
+            ErrorKind::Whitespace => "insignificant whitespace",
+            ErrorKind::UnorderedKey => "unordered key",
+            ErrorKind::DuplicateKey => "repeated key",
+            ErrorKind::Float => "floating point number",
+            ErrorKind::NonFinite => "non-finite number",
+            ErrorKind::LeadingZero => "leading zero",
+            ErrorKind::NegativeZero => "negative zero",
+            ErrorKind::NumberOutOfRange => "number out of range",
+            ErrorKind::NonStringKey => "key must be a string",
+            ErrorKind::NonMinimalEscape => "non-minimal escape",
+            ErrorKind::InvalidEscape => "invalid escape",
+            ErrorKind::TooDeep => "nesting deeper than the limit",
+            ErrorKind::OutputTooLarge => "output larger than the limit",
+            ErrorKind::StringTooLong => "string longer than the limit",
+            ErrorKind::TooManyKeys => "more object members than the limit",
+            ErrorKind::Io => "I/O error",
+            ErrorKind::InvalidDigest => "invalid digest",
+            ErrorKind::SignatureThreshold => "signature threshold not met",
+            ErrorKind::InvalidJws => "invalid JWS",
+            ErrorKind::BadSignature => "bad signature",
+            ErrorKind::InvalidJwk => "invalid JWK",
+            ErrorKind::NotAnObject => "expected a JSON object",
+            ErrorKind::ContentHashMismatch => "content hash mismatch",
+            ErrorKind::InvalidPointer => "invalid JSON Pointer",
+            ErrorKind::MissingPath => "missing path",
+            ErrorKind::InvalidMac => "MAC mismatch",
+            ErrorKind::InvalidKey => "invalid key length",
+            ErrorKind::InvalidToken => "malformed token",
+            ErrorKind::TestFailed => "JSON Patch test failed",
+            ErrorKind::InvalidPatch => "invalid JSON Patch operation",
+            ErrorKind::MergePatchNull => "null member in a JSON Merge Patch",
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str(self.description())
+    }
 }
 
 pub type Result<T> = result::Result<T, Error>;
 
 impl error::Error for Error {
-    fn description(&self) -> &str {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match *self {
-            Error::Custom(..) => "syntax error",
-            Error::Io(ref error) => error::Error::description(error),
+            Error::Custom(..)
+            | Error::Verify(..)
+            | Error::Parse { .. }
+            | Error::Canonical { .. } => None,
+            Error::Io(ref error) => Some(error),
         }
     }
 }
 
+impl Error {
+    /// The message without the `error: ` prefix, for passing on as the
+    /// message of another error.
+    pub(crate) fn detail(&self) -> Detail<'_> {
+        Detail(self)
+    }
+}
+
+pub(crate) struct Detail<'a>(&'a Error);
+
 impl fmt::Display for Error {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         match *self {
-            Error::Custom(ref err) => write!(fmt, "error: {}", err),
             Error::Io(ref error) => fmt::Display::fmt(error, fmt),
+            _ => write!(fmt, "error: {}", self.detail()),
+        }
+    }
+}
+
+impl fmt::Display for Detail<'_> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self.0 {
+            Error::Custom(ref err) => fmt.write_str(err),
+            Error::Io(ref error) => fmt::Display::fmt(error, fmt),
+            Error::Verify(kind) => write!(fmt, "{}", kind),
+            Error::Parse { kind, offset } => write!(fmt, "{} at byte {}", kind, offset),
+            Error::Canonical {
+                kind,
+                ref value,
+                ref pointer,
+            } => {
+                write!(fmt, "{}", kind)?;
+                if !value.is_empty() {
+                    write!(fmt, ": {}", value)?;
+                }
+                if !pointer.is_empty() {
+                    write!(fmt, " at \"{}\"", pointer)?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -37,9 +264,7 @@ impl From<serde_json::Error> for Error {
         use serde_json::error::Category;
         match err.classify() {
             Category::Io => Error::Io(err.into()),
-            Category::Syntax | Category::Data | Category::Eof => {
-                Error::Custom(String::from(format!("{}", err)))
-            }
+            Category::Syntax | Category::Data | Category::Eof => Error::Custom(err.to_string()),
         }
     }
 }
@@ -49,3 +274,9 @@ impl serde::ser::Error for Error {
         Error::Custom(msg.to_string())
     }
 }
+
+impl serde::de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::Custom(msg.to_string())
+    }
+}
@@ -0,0 +1,250 @@
+//! Hashes canonical JSON as it is produced, without holding the document in
+//! memory.
+//!
+//! Any hash implementing `digest::Digest` works. SHA-256 and SHA-512 come
+//! with the `sha2` feature (on by default) and BLAKE3 with the `blake3`
+//! feature. The digest of a value is always the hash of `ser::to_vec` of the
+//! same value.
+//!
+//! `CanonicalDigest` wraps such a hash into the `sha256:<hex>` strings used
+//! to content-address documents.
+
+use super::error::{Error, ErrorKind, Result};
+use super::hex;
+use super::ser::Serializer;
+use serde::{de, Deserialize, Serialize};
+use std::{fmt, io, str};
+
+pub use digest::{Digest, Output};
+
+#[cfg(feature = "blake3")]
+pub use blake3::Hasher as Blake3;
+#[cfg(feature = "sha2")]
+pub use sha2::{Sha256, Sha512};
+
+/// Hash of the canonical form of `value`, as in `to_digest::<Sha256, _>`.
+pub fn to_digest<D, T>(value: &T) -> Result<Output<D>>
+where
+    D: Digest,
+    T: Serialize,
+    T: ?Sized,
+{
+    let mut ser = Serializer::new(HashWriter::<D>::new());
+    value.serialize(&mut ser)?;
+    Ok(ser.into_inner().finalize())
+}
+
+/// An `io::Write` that feeds everything written to it into a hash. Hand it
+/// to a `Serializer` to hash output configured differently from
+/// `to_digest`, such as JCS.
+pub struct HashWriter<D> {
+    hasher: D,
+}
+
+impl<D: Digest> HashWriter<D> {
+    pub fn new() -> Self {
+        HashWriter { hasher: D::new() }
+    }
+
+    /// Hash of everything written so far.
+    pub fn finalize(self) -> Output<D> {
+        self.hasher.finalize()
+    }
+}
+
+impl<D: Digest> Default for HashWriter<D> {
+    fn default() -> Self {
+        HashWriter::new()
+    }
+}
+
+impl<D: Digest> io::Write for HashWriter<D> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.hasher.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A hash function that can back a `CanonicalDigest`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Algorithm {
+    #[cfg(feature = "sha2")]
+    Sha256,
+    #[cfg(feature = "sha2")]
+    Sha512,
+    #[cfg(feature = "blake3")]
+    Blake3,
+}
+
+impl Algorithm {
+    /// The prefix naming the algorithm in a digest string.
+    pub fn name(self) -> &'static str {
+        match self {
+            #[cfg(feature = "sha2")]
+            Algorithm::Sha256 => "sha256",
+            #[cfg(feature = "sha2")]
+            Algorithm::Sha512 => "sha512",
+            #[cfg(feature = "blake3")]
+            Algorithm::Blake3 => "blake3",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            #[cfg(feature = "sha2")]
+            "sha256" => Some(Algorithm::Sha256),
+            #[cfg(feature = "sha2")]
+            "sha512" => Some(Algorithm::Sha512),
+            #[cfg(feature = "blake3")]
+            "blake3" => Some(Algorithm::Blake3),
+            _ => None,
+        }
+    }
+
+    fn output_len(self) -> usize {
+        match self {
+            #[cfg(feature = "sha2")]
+            Algorithm::Sha256 => 32,
+            #[cfg(feature = "sha2")]
+            Algorithm::Sha512 => 64,
+            #[cfg(feature = "blake3")]
+            Algorithm::Blake3 => 32,
+        }
+    }
+
+    #[cfg_attr(
+        not(any(feature = "sha2", feature = "blake3")),
+        allow(unused_variables)
+    )]
+    fn digest<T>(self, value: &T) -> Result<Vec<u8>>
+    where
+        T: Serialize,
+        T: ?Sized,
+    {
+        match self {
+            #[cfg(feature = "sha2")]
+            Algorithm::Sha256 => Ok(to_digest::<Sha256, _>(value)?.to_vec()),
+            #[cfg(feature = "sha2")]
+            Algorithm::Sha512 => Ok(to_digest::<Sha512, _>(value)?.to_vec()),
+            #[cfg(feature = "blake3")]
+            Algorithm::Blake3 => Ok(to_digest::<Blake3, _>(value)?.to_vec()),
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str(self.name())
+    }
+}
+
+/// The hash of a value's canonical form together with the algorithm that
+/// produced it, written the way OCI and TUF do: `sha256:` followed by the
+/// lowercase hex of the hash. Serialized as that string.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct CanonicalDigest {
+    algorithm: Algorithm,
+    bytes: Vec<u8>,
+}
+
+impl CanonicalDigest {
+    pub fn new<T>(algorithm: Algorithm, value: &T) -> Result<Self>
+    where
+        T: Serialize,
+        T: ?Sized,
+    {
+        Ok(CanonicalDigest {
+            algorithm,
+            bytes: algorithm.digest(value)?,
+        })
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Canonicalizes `value` again and checks that it hashes to this digest.
+    /// The hashes are compared in constant time.
+    pub fn verify<T>(&self, value: &T) -> Result<bool>
+    where
+        T: Serialize,
+        T: ?Sized,
+    {
+        Ok(constant_time_eq(
+            &self.algorithm.digest(value)?,
+            &self.bytes,
+        ))
+    }
+}
+
+// Compares two byte strings in time that depends only on their lengths, which
+// are public.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+impl fmt::Display for CanonicalDigest {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{}:{}", self.algorithm, hex::encode(&self.bytes))
+    }
+}
+
+impl str::FromStr for CanonicalDigest {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = |offset| Error::Parse {
+            kind: ErrorKind::InvalidDigest,
+            offset,
+        };
+
+        let colon = s.find(':').ok_or_else(|| invalid(s.len()))?;
+        let algorithm = Algorithm::from_name(&s[..colon]).ok_or_else(|| invalid(0))?;
+        if s.len() - colon - 1 != algorithm.output_len() * 2 {
+            return Err(invalid(colon + 1));
+        }
+        let bytes = hex::decode(&s[colon + 1..]).map_err(|i| invalid(colon + 1 + i))?;
+
+        Ok(CanonicalDigest { algorithm, bytes })
+    }
+}
+
+impl Serialize for CanonicalDigest {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for CanonicalDigest {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = CanonicalDigest;
+
+            fn expecting(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt.write_str("a digest such as \"sha256:<hex>\"")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> std::result::Result<CanonicalDigest, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_str(Visitor)
+    }
+}
@@ -0,0 +1,29 @@
+//! Lowercase hexadecimal, as used by digests and signatures.
+
+use std::fmt::Write;
+
+pub(crate) fn encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(out, "{:02x}", byte).expect("writing to a String");
+    }
+    out
+}
+
+/// Decodes lowercase hex. On failure, returns the offset of the first byte
+/// that is not a lowercase hex digit, or the length of an odd-length input.
+pub(crate) fn decode(hex: &str) -> Result<Vec<u8>, usize> {
+    let hex = hex.as_bytes();
+    if !hex.len().is_multiple_of(2) {
+        return Err(hex.len());
+    }
+    let nibble = |i: usize| match hex[i] {
+        b @ b'0'..=b'9' => Ok(b - b'0'),
+        b @ b'a'..=b'f' => Ok(b - b'a' + 10),
+        _ => Err(i),
+    };
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| Ok(nibble(i)? << 4 | nibble(i + 1)?))
+        .collect()
+}
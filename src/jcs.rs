@@ -0,0 +1,189 @@
+//! RFC 8785 JSON Canonicalization Scheme (JCS).
+//!
+//! The functions in this module produce the same output as their `ser`
+//! counterparts, except that the serializer is configured with
+//! [`Scheme::Jcs`](../ser/enum.Scheme.html): object keys are sorted by their
+//! UTF-16 code units, numbers are written with the ECMAScript shortest
+//! round-trip algorithm and strings use the escaping rules of RFC 8785.
+
+use super::error::{Error, ErrorKind, Result};
+use super::ser::{self, Scheme, Serializer};
+use serde::Serialize;
+use std::io;
+
+pub fn to_writer<W, T>(writer: W, value: &T) -> Result<()>
+where
+    T: Serialize,
+    T: ?Sized,
+    W: io::Write,
+{
+    ser::write_rendered(
+        writer,
+        Serializer::with_scheme(Vec::new(), Scheme::Jcs),
+        value,
+    )
+}
+
+pub fn to_vec<T>(value: &T) -> Result<Vec<u8>>
+where
+    T: Serialize,
+    T: ?Sized,
+{
+    ser::render(
+        Serializer::with_scheme(Vec::with_capacity(128), Scheme::Jcs),
+        value,
+    )
+}
+
+pub fn to_string<T>(value: &T) -> Result<String>
+where
+    T: Serialize,
+    T: ?Sized,
+{
+    Ok(unsafe { String::from_utf8_unchecked(to_vec(value)?) })
+}
+
+// Largest magnitude up to which every integer is exactly representable as an
+// IEEE 754 double.
+const MAX_EXACT_INTEGER: i128 = 1 << 53;
+
+/// Writes an integer as the ECMAScript number with the same value. Integers
+/// that cannot be represented exactly as a double are rejected rather than
+/// silently rounded.
+pub(crate) fn write_integer<W: io::Write>(writer: &mut W, v: i128) -> Result<()> {
+    if (-MAX_EXACT_INTEGER..=MAX_EXACT_INTEGER).contains(&v) {
+        itoa::write(writer, v as i64)?;
+        return Ok(());
+    }
+
+    if !is_exact(v.unsigned_abs()) {
+        return Err(Error::canonical(ErrorKind::NumberOutOfRange, v));
+    }
+    write_f64(writer, v as f64)
+}
+
+/// `write_integer` for unsigned values beyond the range of `i128`.
+pub(crate) fn write_u128<W: io::Write>(writer: &mut W, v: u128) -> Result<()> {
+    if !is_exact(v) {
+        return Err(Error::canonical(ErrorKind::NumberOutOfRange, v));
+    }
+    write_f64(writer, v as f64)
+}
+
+// A double holds an integer exactly when its significant bits, from the
+// highest set bit to the lowest, fit in the 53-bit significand.
+fn is_exact(magnitude: u128) -> bool {
+    magnitude == 0 || 128 - magnitude.leading_zeros() - magnitude.trailing_zeros() <= 53
+}
+
+/// Writes a finite double using the ECMAScript `Number.prototype.toString`
+/// algorithm (ECMA-262, section 7.1.12.1), as required by RFC 8785.
+pub(crate) fn write_f64<W: io::Write>(writer: &mut W, v: f64) -> Result<()> {
+    if !v.is_finite() {
+        return Err(Error::canonical(ErrorKind::NonFinite, v));
+    }
+    writer.write_all(format_f64(v).as_bytes())?;
+    Ok(())
+}
+
+fn format_f64(v: f64) -> String {
+    // both zeroes are written as "0"
+    if v == 0.0 {
+        return String::from("0");
+    }
+
+    // Rust formats floats with the shortest digit string that round-trips,
+    // which gives the `k` ECMAScript asks for. When two such strings are
+    // equally close to the value ECMAScript wants the even one, so the digits
+    // are taken from the correctly rounded (half to even) `k`-digit form.
+    let (digits, _) = split_exp(&format!("{:e}", v.abs()));
+    let k = digits.len() as i32;
+    let (digits, exponent) = split_exp(&format!("{:.*e}", k as usize - 1, v.abs()));
+
+    let n = exponent + 1;
+
+    let mut out = String::with_capacity(k as usize + 8);
+    if v < 0.0 {
+        out.push('-');
+    }
+
+    if k <= n && n <= 21 {
+        out.push_str(&digits);
+        out.extend((0..n - k).map(|_| '0'));
+    } else if 0 < n && n <= 21 {
+        out.push_str(&digits[..n as usize]);
+        out.push('.');
+        out.push_str(&digits[n as usize..]);
+    } else if -6 < n && n <= 0 {
+        out.push_str("0.");
+        out.extend((0..-n).map(|_| '0'));
+        out.push_str(&digits);
+    } else {
+        out.push_str(&digits[..1]);
+        if k > 1 {
+            out.push('.');
+            out.push_str(&digits[1..]);
+        }
+        out.push('e');
+        out.push(if n - 1 < 0 { '-' } else { '+' });
+        out.push_str(&(n - 1).abs().to_string());
+    }
+
+    out
+}
+
+// Splits `d.ddde±x` into its significant digits and its exponent.
+fn split_exp(sci: &str) -> (String, i32) {
+    let e = sci.find('e').expect("exponent in scientific notation");
+    let digits = sci[..e].chars().filter(char::is_ascii_digit).collect();
+    let exponent = sci[e + 1..].parse().expect("integer exponent");
+    (digits, exponent)
+}
+
+/// Writes a string literal with the escaping rules of RFC 8785, section
+/// 3.2.2.2: `"` and `\` are escaped, control characters use the short forms
+/// where JSON defines one and `\u00xx` otherwise, everything else is written
+/// as-is.
+pub(crate) fn escape_str<W: io::Write>(writer: &mut W, v: &str) -> Result<()> {
+    writer.write_all(b"\"")?;
+
+    let bytes = v.as_bytes();
+    let mut start = 0;
+
+    for (i, &byte) in bytes.iter().enumerate() {
+        let escape: &[u8] = match byte {
+            b'"' => b"\\\"",
+            b'\\' => b"\\\\",
+            b'\x08' => b"\\b",
+            b'\x0c' => b"\\f",
+            b'\n' => b"\\n",
+            b'\r' => b"\\r",
+            b'\t' => b"\\t",
+            0x00..=0x1f => {
+                if start < i {
+                    writer.write_all(&bytes[start..i])?;
+                }
+                write!(writer, "\\u{:04x}", byte)?;
+                start = i + 1;
+                continue;
+            }
+            _ => continue,
+        };
+
+        if start < i {
+            writer.write_all(&bytes[start..i])?;
+        }
+
+        writer.write_all(escape)?;
+
+        start = i + 1;
+    }
+
+    if start != bytes.len() {
+        writer.write_all(&bytes[start..])?;
+    }
+
+    writer.write_all(b"\"")?;
+
+    Ok(())
+}
@@ -0,0 +1,68 @@
+//! JSON Web Key thumbprints (RFC 7638).
+//!
+//! A thumbprint is the SHA-256 of a JWK reduced to the members its key type
+//! requires, written with sorted keys and no whitespace. That is the canonical
+//! form `ser` produces, so the hash is taken as the members are serialized.
+
+use super::error::{Error, ErrorKind, Result};
+use super::hash::{to_digest, Output, Sha256};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// The prefix of the thumbprint URI (RFC 9278) of a SHA-256 thumbprint.
+pub const THUMBPRINT_URI_PREFIX: &str = "urn:ietf:params:oauth:jwk-thumbprint:sha-256:";
+
+/// A JSON Web Key. Members other than `kty` are kept as they are.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Jwk {
+    pub kty: String,
+    #[serde(flatten)]
+    pub params: BTreeMap<String, Value>,
+}
+
+// Members that take part in the thumbprint besides `kty`, by key type
+// (RFC 7638, section 3.2, and RFC 8037, section 2).
+fn required_members(kty: &str) -> Option<&'static [&'static str]> {
+    match kty {
+        "RSA" => Some(&["e", "n"]),
+        "EC" => Some(&["crv", "x", "y"]),
+        "OKP" => Some(&["crv", "x"]),
+        "oct" => Some(&["k"]),
+        _ => None,
+    }
+}
+
+/// The SHA-256 thumbprint of `jwk`. Fails with `ErrorKind::InvalidJwk` when
+/// the key type is unknown or a required member is missing or not a string.
+pub fn jwk_thumbprint(jwk: &Jwk) -> Result<Output<Sha256>> {
+    let names = required_members(&jwk.kty)
+        .ok_or_else(|| Error::canonical(ErrorKind::InvalidJwk, &jwk.kty).at("kty"))?;
+
+    let mut members = BTreeMap::new();
+    members.insert("kty", jwk.kty.as_str());
+    for &name in names {
+        match jwk.params.get(name) {
+            Some(Value::String(value)) => members.insert(name, value.as_str()),
+            Some(value) => return Err(Error::canonical(ErrorKind::InvalidJwk, value).at(name)),
+            None => return Err(Error::canonical(ErrorKind::InvalidJwk, "missing").at(name)),
+        };
+    }
+
+    to_digest::<Sha256, _>(&members)
+}
+
+/// `jwk_thumbprint` in base64url without padding, as the RFC writes it.
+pub fn jwk_thumbprint_base64url(jwk: &Jwk) -> Result<String> {
+    Ok(URL_SAFE_NO_PAD.encode(jwk_thumbprint(jwk)?))
+}
+
+/// `jwk_thumbprint` as a `urn:ietf:params:oauth:jwk-thumbprint` URI.
+pub fn jwk_thumbprint_uri(jwk: &Jwk) -> Result<String> {
+    Ok(format!(
+        "{}{}",
+        THUMBPRINT_URI_PREFIX,
+        jwk_thumbprint_base64url(jwk)?
+    ))
+}
@@ -0,0 +1,419 @@
+//! JSON Web Signatures (RFC 7515) over the canonical form of a value.
+//!
+//! The payload is always `ser::to_vec` of the value, and so is the protected
+//! header. Verification deserializes the received payload and canonicalizes it
+//! again instead of trusting the bytes that came with it, so a signed object
+//! that was re-serialized along the way still verifies.
+//!
+//! Each algorithm sits behind a feature of the same name: `hs256`, `es256`
+//! and `eddsa`.
+
+use super::error::{Error, ErrorKind, Result};
+use super::ser;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_derive::{Deserialize, Serialize};
+
+/// How the payload travels with the signature.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Form {
+    /// Base64url-encoded and included in the JWS.
+    Attached,
+    /// Base64url-encoded for signing but left out of the JWS (RFC 7515,
+    /// appendix F). The verifier supplies the value.
+    Detached,
+    /// Signed as is with the `b64` header set to `false` (RFC 7797) and left
+    /// out of the JWS, which saves encoding large payloads.
+    Unencoded,
+}
+
+/// A JWS `alg` this build supports.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Algorithm {
+    /// HMAC with SHA-256.
+    #[cfg(feature = "hs256")]
+    HS256,
+    /// ECDSA with P-256 and SHA-256.
+    #[cfg(feature = "es256")]
+    ES256,
+    /// Ed25519.
+    #[cfg(feature = "eddsa")]
+    EdDSA,
+}
+
+impl Algorithm {
+    pub fn name(self) -> &'static str {
+        match self {
+            #[cfg(feature = "hs256")]
+            Algorithm::HS256 => "HS256",
+            #[cfg(feature = "es256")]
+            Algorithm::ES256 => "ES256",
+            #[cfg(feature = "eddsa")]
+            Algorithm::EdDSA => "EdDSA",
+        }
+    }
+}
+
+/// A key to sign with.
+pub enum SigningKey {
+    /// An HMAC secret.
+    #[cfg(feature = "hs256")]
+    HS256(Vec<u8>),
+    #[cfg(feature = "es256")]
+    ES256(p256::ecdsa::SigningKey),
+    #[cfg(feature = "eddsa")]
+    EdDSA(ed25519_dalek::SigningKey),
+}
+
+/// A key to verify with. For HS256 this is the shared secret.
+pub enum VerifyingKey {
+    #[cfg(feature = "hs256")]
+    HS256(Vec<u8>),
+    #[cfg(feature = "es256")]
+    ES256(p256::ecdsa::VerifyingKey),
+    #[cfg(feature = "eddsa")]
+    EdDSA(ed25519_dalek::VerifyingKey),
+}
+
+impl SigningKey {
+    pub fn algorithm(&self) -> Algorithm {
+        match *self {
+            #[cfg(feature = "hs256")]
+            SigningKey::HS256(..) => Algorithm::HS256,
+            #[cfg(feature = "es256")]
+            SigningKey::ES256(..) => Algorithm::ES256,
+            #[cfg(feature = "eddsa")]
+            SigningKey::EdDSA(..) => Algorithm::EdDSA,
+        }
+    }
+
+    pub fn verifying_key(&self) -> VerifyingKey {
+        match *self {
+            #[cfg(feature = "hs256")]
+            SigningKey::HS256(ref secret) => VerifyingKey::HS256(secret.clone()),
+            #[cfg(feature = "es256")]
+            SigningKey::ES256(ref key) => VerifyingKey::ES256(*key.verifying_key()),
+            #[cfg(feature = "eddsa")]
+            SigningKey::EdDSA(ref key) => VerifyingKey::EdDSA(key.verifying_key()),
+        }
+    }
+
+    pub(crate) fn sign(&self, input: &[u8]) -> Vec<u8> {
+        match *self {
+            #[cfg(feature = "hs256")]
+            SigningKey::HS256(ref secret) => {
+                use hmac::Mac;
+                let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(secret)
+                    .expect("HMAC takes keys of any length");
+                mac.update(input);
+                mac.finalize().into_bytes().to_vec()
+            }
+            #[cfg(feature = "es256")]
+            SigningKey::ES256(ref key) => {
+                use p256::ecdsa::signature::Signer;
+                let signature: p256::ecdsa::Signature = key.sign(input);
+                signature.to_bytes().to_vec()
+            }
+            #[cfg(feature = "eddsa")]
+            SigningKey::EdDSA(ref key) => {
+                use ed25519_dalek::Signer;
+                key.sign(input).to_vec()
+            }
+        }
+    }
+}
+
+impl VerifyingKey {
+    pub fn algorithm(&self) -> Algorithm {
+        match *self {
+            #[cfg(feature = "hs256")]
+            VerifyingKey::HS256(..) => Algorithm::HS256,
+            #[cfg(feature = "es256")]
+            VerifyingKey::ES256(..) => Algorithm::ES256,
+            #[cfg(feature = "eddsa")]
+            VerifyingKey::EdDSA(..) => Algorithm::EdDSA,
+        }
+    }
+
+    fn verify(&self, input: &[u8], signature: &[u8]) -> bool {
+        match *self {
+            #[cfg(feature = "hs256")]
+            VerifyingKey::HS256(ref secret) => {
+                use hmac::Mac;
+                let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(secret)
+                    .expect("HMAC takes keys of any length");
+                mac.update(input);
+                mac.verify_slice(signature).is_ok()
+            }
+            #[cfg(feature = "es256")]
+            VerifyingKey::ES256(ref key) => {
+                use p256::ecdsa::signature::Verifier;
+                match p256::ecdsa::Signature::from_slice(signature) {
+                    Ok(signature) => key.verify(input, &signature).is_ok(),
+                    Err(_) => false,
+                }
+            }
+            #[cfg(feature = "eddsa")]
+            VerifyingKey::EdDSA(ref key) => {
+                use ed25519_dalek::Verifier;
+                match ed25519_dalek::Signature::from_slice(signature) {
+                    Ok(signature) => key.verify(input, &signature).is_ok(),
+                    Err(_) => false,
+                }
+            }
+        }
+    }
+}
+
+/// A JWS in the general JSON serialization (RFC 7515, section 7.2.1), which
+/// can carry signatures by several keys.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct JsonJws {
+    /// Base64url-encoded payload, absent for the detached forms.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<String>,
+    pub signatures: Vec<JsonSignature>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct JsonSignature {
+    /// Base64url-encoded protected header.
+    pub protected: String,
+    /// Base64url-encoded signature.
+    pub signature: String,
+}
+
+// The protected header. Members a verifier does not need are ignored, but
+// anything listed in `crit` other than `b64` is refused.
+#[derive(Deserialize, Serialize)]
+struct Header<'a> {
+    alg: &'a str,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    b64: Option<bool>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    crit: Vec<String>,
+}
+
+fn invalid() -> Error {
+    Error::Verify(ErrorKind::InvalidJws)
+}
+
+fn encode(bytes: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn decode(text: &str) -> Result<Vec<u8>> {
+    URL_SAFE_NO_PAD.decode(text).map_err(|_| invalid())
+}
+
+fn protected_header(algorithm: Algorithm, form: Form) -> Result<String> {
+    let header = match form {
+        Form::Attached | Form::Detached => Header {
+            alg: algorithm.name(),
+            b64: None,
+            crit: Vec::new(),
+        },
+        Form::Unencoded => Header {
+            alg: algorithm.name(),
+            b64: Some(false),
+            crit: vec!["b64".to_string()],
+        },
+    };
+    Ok(encode(&ser::to_vec(&header)?))
+}
+
+// `ASCII(BASE64URL(header)) || '.' || payload`, with the payload
+// base64url-encoded unless the header says `b64: false`.
+pub(crate) fn signing_input(protected: &str, payload: &[u8], encoded: bool) -> Vec<u8> {
+    let mut input = Vec::with_capacity(protected.len() + 1 + payload.len() * 4 / 3 + 4);
+    input.extend_from_slice(protected.as_bytes());
+    input.push(b'.');
+    if encoded {
+        input.extend_from_slice(encode(payload).as_bytes());
+    } else {
+        input.extend_from_slice(payload);
+    }
+    input
+}
+
+fn sign_parts<T>(value: &T, key: &SigningKey, form: Form) -> Result<(String, String, String)>
+where
+    T: Serialize,
+    T: ?Sized,
+{
+    let payload = ser::to_vec(value)?;
+    let protected = protected_header(key.algorithm(), form)?;
+    let signature = key.sign(&signing_input(
+        &protected,
+        &payload,
+        form != Form::Unencoded,
+    ));
+    let payload = match form {
+        Form::Attached => encode(&payload),
+        Form::Detached | Form::Unencoded => String::new(),
+    };
+    Ok((protected, payload, encode(&signature)))
+}
+
+// Whether the header says the payload is base64url-encoded, after checking
+// that it is well formed.
+fn parse_header(protected: &str) -> Result<(String, bool)> {
+    let bytes = decode(protected)?;
+    let header: Header<'_> = serde_json::from_slice(&bytes).map_err(|_| invalid())?;
+    if header.crit.iter().any(|name| name != "b64") {
+        return Err(invalid());
+    }
+    let encoded = match header.b64 {
+        Some(false) if header.crit.iter().any(|name| name == "b64") => false,
+        Some(false) => return Err(invalid()),
+        _ => true,
+    };
+    Ok((header.alg.to_string(), encoded))
+}
+
+// Checks one signature over `payload`, the canonical form of the value.
+// Signatures made with another algorithm than the key's do not verify.
+fn check(
+    key: &VerifyingKey,
+    protected: &str,
+    signature: &str,
+    payload: &[u8],
+    attached: bool,
+) -> Result<bool> {
+    let (alg, encoded) = parse_header(protected)?;
+    if attached && !encoded {
+        return Err(invalid());
+    }
+    let signature = decode(signature)?;
+    Ok(alg == key.algorithm().name()
+        && key.verify(&signing_input(protected, payload, encoded), &signature))
+}
+
+fn read_payload<T: DeserializeOwned + Serialize>(payload: &str) -> Result<(T, Vec<u8>)> {
+    let value: T = serde_json::from_slice(&decode(payload)?).map_err(|_| invalid())?;
+    let canonical = ser::to_vec(&value)?;
+    Ok((value, canonical))
+}
+
+fn split_compact(jws: &str) -> Result<(&str, &str, &str)> {
+    let mut parts = jws.split('.');
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(protected), Some(payload), Some(signature), None) => {
+            Ok((protected, payload, signature))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+/// Signs the canonical form of `value` into a compact JWS
+/// (`header.payload.signature`), whose payload part is empty for the
+/// detached forms.
+pub fn sign_compact<T>(value: &T, key: &SigningKey, form: Form) -> Result<String>
+where
+    T: Serialize,
+    T: ?Sized,
+{
+    let (protected, payload, signature) = sign_parts(value, key, form)?;
+    Ok(format!("{}.{}.{}", protected, payload, signature))
+}
+
+/// Verifies a compact JWS with an attached payload and returns the payload.
+pub fn verify_compact<T>(jws: &str, key: &VerifyingKey) -> Result<T>
+where
+    T: DeserializeOwned + Serialize,
+{
+    let (protected, payload, signature) = split_compact(jws)?;
+    if payload.is_empty() {
+        return Err(invalid());
+    }
+    let (value, canonical) = read_payload(payload)?;
+    if !check(key, protected, signature, &canonical, true)? {
+        return Err(Error::Verify(ErrorKind::BadSignature));
+    }
+    Ok(value)
+}
+
+/// Verifies a compact JWS with a detached or unencoded payload against
+/// `value`.
+pub fn verify_compact_detached<T>(jws: &str, value: &T, key: &VerifyingKey) -> Result<()>
+where
+    T: Serialize,
+    T: ?Sized,
+{
+    let (protected, payload, signature) = split_compact(jws)?;
+    if !payload.is_empty() {
+        return Err(invalid());
+    }
+    if !check(key, protected, signature, &ser::to_vec(value)?, false)? {
+        return Err(Error::Verify(ErrorKind::BadSignature));
+    }
+    Ok(())
+}
+
+/// Signs the canonical form of `value` with each of `keys` into a JWS in
+/// the general JSON serialization.
+pub fn sign_json<T>(value: &T, keys: &[SigningKey], form: Form) -> Result<JsonJws>
+where
+    T: Serialize,
+    T: ?Sized,
+{
+    let mut jws = JsonJws {
+        payload: None,
+        signatures: Vec::with_capacity(keys.len()),
+    };
+    for key in keys {
+        let (protected, payload, signature) = sign_parts(value, key, form)?;
+        if form == Form::Attached {
+            jws.payload = Some(payload);
+        }
+        jws.signatures.push(JsonSignature {
+            protected,
+            signature,
+        });
+    }
+    Ok(jws)
+}
+
+// Whether any of the signatures verifies with `key`. A malformed entry is
+// one that does not verify, so it cannot hide a valid one after it.
+fn check_any(key: &VerifyingKey, jws: &JsonJws, payload: &[u8], attached: bool) -> Result<()> {
+    for signature in &jws.signatures {
+        if check(
+            key,
+            &signature.protected,
+            &signature.signature,
+            payload,
+            attached,
+        )
+        .unwrap_or(false)
+        {
+            return Ok(());
+        }
+    }
+    Err(Error::Verify(ErrorKind::BadSignature))
+}
+
+/// Verifies a JSON-serialized JWS with an attached payload and returns the
+/// payload. One valid signature by `key` is enough.
+pub fn verify_json<T>(jws: &JsonJws, key: &VerifyingKey) -> Result<T>
+where
+    T: DeserializeOwned + Serialize,
+{
+    let payload = jws.payload.as_ref().ok_or_else(invalid)?;
+    let (value, canonical) = read_payload(payload)?;
+    check_any(key, jws, &canonical, true)?;
+    Ok(value)
+}
+
+/// Verifies a JSON-serialized JWS with a detached or unencoded payload
+/// against `value`.
+pub fn verify_json_detached<T>(jws: &JsonJws, value: &T, key: &VerifyingKey) -> Result<()>
+where
+    T: Serialize,
+    T: ?Sized,
+{
+    if jws.payload.is_some() {
+        return Err(invalid());
+    }
+    check_any(key, jws, &ser::to_vec(value)?, false)
+}
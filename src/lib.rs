@@ -2,8 +2,30 @@
 #![warn(rust_2018_idioms)]
 
 pub mod canonical_value;
+pub mod de;
+pub mod diff;
 pub mod error;
+#[cfg(feature = "digest")]
+pub mod hash;
+#[cfg(feature = "digest")]
+mod hex;
+pub mod jcs;
+#[cfg(feature = "jwk")]
+pub mod jwk;
+#[cfg(any(feature = "hs256", feature = "es256", feature = "eddsa"))]
+pub mod jws;
+pub mod limits;
+#[cfg(feature = "mac")]
+pub mod mac;
+pub mod matrix;
+pub mod patch;
+pub mod projection;
+pub mod securesystemslib;
 pub mod ser;
+pub mod stream;
+#[cfg(feature = "sha2")]
+pub mod tuf;
+pub mod validate;
 
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,73 @@
+//! Bounds on the size and shape of documents, for working with untrusted
+//! input.
+//!
+//! A `Limits` is handed to the `Serializer`, the strict `Deserializer` or the
+//! streaming canonicalizer. Going over any of them stops the work with an
+//! error of kind `TooDeep`, `OutputTooLarge`, `StringTooLong` or
+//! `TooManyKeys`, before the offending part is fully built in memory. The
+//! validator reports the same kinds as violations.
+//!
+//! Everything that recurses is bounded by default: `Limits::default()` caps
+//! the depth at `DEFAULT_MAX_DEPTH` and sets nothing else.
+
+/// The depth `Limits::default()` allows, the same as `serde_json` does.
+pub const DEFAULT_MAX_DEPTH: usize = 128;
+
+/// Limits on a document. The default only caps the depth.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Limits {
+    pub(crate) max_depth: Option<usize>,
+    pub(crate) max_output_bytes: Option<usize>,
+    pub(crate) max_string_len: Option<usize>,
+    pub(crate) max_keys: Option<usize>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_depth: Some(DEFAULT_MAX_DEPTH),
+            ..Limits::none()
+        }
+    }
+}
+
+impl Limits {
+    /// No limits at all, not even on depth. Only for trusted input: deep
+    /// enough nesting overflows the stack.
+    pub fn none() -> Self {
+        Limits {
+            max_depth: None,
+            max_output_bytes: None,
+            max_string_len: None,
+            max_keys: None,
+        }
+    }
+
+    /// Maximum number of arrays and objects open at the same time. A scalar
+    /// is at depth 0 and `[[1]]` at depth 2. This also bounds recursion, so
+    /// a hostile document cannot overflow the stack.
+    pub fn max_depth(mut self, max: usize) -> Self {
+        self.max_depth = Some(max);
+        self
+    }
+
+    /// Maximum size in bytes of the canonical output, counting object
+    /// members while they are held back for sorting. Only the serializer
+    /// produces output; the parser ignores this limit.
+    pub fn max_output_bytes(mut self, max: usize) -> Self {
+        self.max_output_bytes = Some(max);
+        self
+    }
+
+    /// Maximum length in bytes of a string or object key, before escaping.
+    pub fn max_string_len(mut self, max: usize) -> Self {
+        self.max_string_len = Some(max);
+        self
+    }
+
+    /// Maximum number of members in a single object.
+    pub fn max_keys(mut self, max: usize) -> Self {
+        self.max_keys = Some(max);
+        self
+    }
+}
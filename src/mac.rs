@@ -0,0 +1,118 @@
+//! Keyed MACs over canonical JSON, and tokens that carry a value together
+//! with its MAC.
+//!
+//! `mac` streams the output of the serializer into the MAC, so two services
+//! that agree on the key agree on the tag of a value however they built it.
+//! A token is `base64url(payload).base64url(tag)` without padding, where the
+//! payload is the canonical form of the value; `decode` checks the tag before
+//! it looks at the payload, and then only accepts a canonical one.
+
+use super::de;
+use super::error::{Error, ErrorKind, Result};
+use super::ser::{self, Serializer};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::digest::{KeyInit, Output};
+use serde::{de::DeserializeOwned, Serialize};
+use std::io;
+
+pub use hmac::{Hmac, Mac};
+
+/// HMAC with SHA-256.
+pub type HmacSha256 = Hmac<sha2::Sha256>;
+/// HMAC with SHA-512.
+pub type HmacSha512 = Hmac<sha2::Sha512>;
+
+// Feeds everything written to it into a MAC.
+struct MacWriter<M> {
+    mac: M,
+}
+
+impl<M: Mac> io::Write for MacWriter<M> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.mac.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn keyed<M: Mac + KeyInit>(key: &[u8]) -> Result<M> {
+    <M as Mac>::new_from_slice(key).map_err(|_| Error::Verify(ErrorKind::InvalidKey))
+}
+
+fn canonical_mac<M, T>(key: &[u8], value: &T) -> Result<M>
+where
+    M: Mac + KeyInit,
+    T: Serialize + ?Sized,
+{
+    let mut ser = Serializer::new(MacWriter {
+        mac: keyed::<M>(key)?,
+    });
+    value.serialize(&mut ser)?;
+    Ok(ser.into_inner().mac)
+}
+
+/// The MAC of the canonical form of `value` under `key`, such as
+/// `mac::<HmacSha256>(key, &value)`. Fails with `ErrorKind::InvalidKey` when
+/// `M` does not take keys of that length.
+pub fn mac<M>(key: &[u8], value: &(impl Serialize + ?Sized)) -> Result<Output<M>>
+where
+    M: Mac + KeyInit,
+{
+    Ok(canonical_mac::<M, _>(key, value)?.finalize().into_bytes())
+}
+
+/// Canonicalizes `value` again and checks it against `tag` in constant
+/// time. Fails with `ErrorKind::InvalidMac` when they differ.
+pub fn verify_mac<M>(key: &[u8], value: &(impl Serialize + ?Sized), tag: &[u8]) -> Result<()>
+where
+    M: Mac + KeyInit,
+{
+    canonical_mac::<M, _>(key, value)?
+        .verify_slice(tag)
+        .map_err(|_| Error::Verify(ErrorKind::InvalidMac))
+}
+
+/// A token carrying the canonical form of `value` and its MAC.
+pub fn encode<M, T>(key: &[u8], value: &T) -> Result<String>
+where
+    M: Mac + KeyInit,
+    T: Serialize + ?Sized,
+{
+    let payload = ser::to_vec(value)?;
+    let mut mac = keyed::<M>(key)?;
+    mac.update(&payload);
+    Ok(format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(&payload),
+        URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+    ))
+}
+
+/// The value in a token made by `encode` with the same key. A token that is
+/// malformed fails with `ErrorKind::InvalidToken`, one whose tag does not
+/// match with `ErrorKind::InvalidMac`, and one whose payload is not
+/// canonical JSON with the error of the strict parser.
+pub fn decode<M, T>(key: &[u8], token: &str) -> Result<T>
+where
+    M: Mac + KeyInit,
+    T: DeserializeOwned,
+{
+    let invalid = || Error::Verify(ErrorKind::InvalidToken);
+    let mut parts = token.split('.');
+    let (payload, tag) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(payload), Some(tag), None) => (payload, tag),
+        _ => return Err(invalid()),
+    };
+    let payload = URL_SAFE_NO_PAD.decode(payload).map_err(|_| invalid())?;
+    let tag = URL_SAFE_NO_PAD.decode(tag).map_err(|_| invalid())?;
+
+    let mut mac = keyed::<M>(key)?;
+    mac.update(&payload);
+    mac.verify_slice(&tag)
+        .map_err(|_| Error::Verify(ErrorKind::InvalidMac))?;
+
+    de::from_slice(&payload)
+}
@@ -1,31 +1,48 @@
 extern crate serde_canonical;
-extern crate serde_json;
 
-use std::{env, fs, path};
+use serde_json::Value;
+use std::{env, fs, io, path, process};
 
 const ARG_PANIC_MESSAGE: &str =
-    "The first argument is the input JSON, and the second is an optional output file.";
+    "The first argument is the input JSON, and the second is an optional output file. \
+     `diff <a> <b>` compares two JSON files.";
 
 fn main() {
     let args: Vec<String> = env::args().collect();
+    if args.len() == 4 && args[1] == "diff" {
+        diff(&args[2], &args[3]);
+    }
     let input = &args[1];
     let input = fs::File::open(path::Path::new(input)).expect("cannot open input file");
-    let res: serde_json::Value =
-        serde_json::from_reader(input).expect("cannot deserialize input file");
+    let input = io::BufReader::new(input);
     match args.len() {
         2 => {
-            print!(
-                "{}",
-                serde_canonical::ser::to_string(&res).expect("cannot write canonical JSON")
-            );
+            let stdout = io::stdout();
+            let output = io::BufWriter::new(stdout.lock());
+            serde_canonical::stream::canonicalize(input, output)
+                .expect("cannot write canonical JSON");
         }
         3 => {
             let output = &args[2];
-            let mut output = fs::File::create(path::Path::new(output))
+            let output = fs::File::create(path::Path::new(output))
                 .expect("cannot create or open output file");
-            serde_canonical::ser::to_writer(&mut output, &res)
+            serde_canonical::stream::canonicalize(input, io::BufWriter::new(output))
                 .expect("cannot write canonical JSON");
         }
-        _ => panic!(ARG_PANIC_MESSAGE),
+        _ => panic!("{}", ARG_PANIC_MESSAGE),
     };
 }
+
+fn read_json(input: &str) -> Value {
+    let input = fs::File::open(path::Path::new(input)).expect("cannot open input file");
+    serde_json::from_reader(io::BufReader::new(input)).expect("cannot parse input JSON")
+}
+
+// Prints how two documents differ and exits with 0 when their canonical
+// forms are equal and 1 otherwise, like diff(1).
+fn diff(a: &str, b: &str) -> ! {
+    let diff = serde_canonical::diff::diff(&read_json(a), &read_json(b))
+        .expect("cannot canonicalize input JSON");
+    println!("{}", diff);
+    process::exit(if diff.is_equal() { 0 } else { 1 })
+}
@@ -0,0 +1,302 @@
+//! Matrix canonical JSON, event redaction, content hashes and signatures.
+//!
+//! The functions in this module serialize with
+//! [`Scheme::Matrix`](../ser/enum.Scheme.html): keys sorted by code point,
+//! non-ASCII text unescaped, floats rejected and integers limited to the
+//! range the Matrix specification allows.
+//!
+//! Signing and verifying JSON objects always leaves out their `signatures`
+//! and `unsigned` members, and events are signed in their redacted form, as
+//! the server-server API requires. Content hashes and Ed25519 signatures come
+//! with the `matrix` feature.
+
+use super::error::{Error, ErrorKind, Result};
+use super::ser::{self, Scheme, Serializer};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::io;
+
+#[cfg(feature = "matrix")]
+use super::hash::{HashWriter, Output, Sha256};
+#[cfg(feature = "matrix")]
+use base64::{
+    alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    Engine,
+};
+#[cfg(feature = "matrix")]
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+
+pub fn to_writer<W, T>(writer: W, value: &T) -> Result<()>
+where
+    T: Serialize,
+    T: ?Sized,
+    W: io::Write,
+{
+    ser::write_rendered(writer, serializer(Vec::new()), value)
+}
+
+pub fn to_vec<T>(value: &T) -> Result<Vec<u8>>
+where
+    T: Serialize,
+    T: ?Sized,
+{
+    ser::render(serializer(Vec::with_capacity(128)), value)
+}
+
+pub fn to_string<T>(value: &T) -> Result<String>
+where
+    T: Serialize,
+    T: ?Sized,
+{
+    Ok(unsafe { String::from_utf8_unchecked(to_vec(value)?) })
+}
+
+/// A `Serializer` for Matrix canonical JSON.
+pub fn serializer<W: io::Write>(writer: W) -> Serializer<W> {
+    Serializer::with_scheme(writer, Scheme::Matrix)
+}
+
+fn as_object(value: &Value) -> Result<&Map<String, Value>> {
+    value
+        .as_object()
+        .ok_or_else(|| Error::canonical(ErrorKind::NotAnObject, value))
+}
+
+#[cfg(feature = "matrix")]
+fn as_object_mut(value: &mut Value) -> Result<&mut Map<String, Value>> {
+    match *value {
+        Value::Object(ref mut object) => Ok(object),
+        _ => Err(Error::canonical(ErrorKind::NotAnObject, &*value)),
+    }
+}
+
+/// The bytes signatures of `object` are made over: its canonical form
+/// without the `signatures` and `unsigned` members.
+pub fn signable_bytes(object: &Value) -> Result<Vec<u8>> {
+    let mut object = as_object(object)?.clone();
+    object.remove("signatures");
+    object.remove("unsigned");
+    to_vec(&object)
+}
+
+/// A room version, which decides what redaction keeps.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum RoomVersion {
+    V1,
+    V2,
+    V3,
+    V4,
+    V5,
+    V6,
+    V7,
+    V8,
+    V9,
+    V10,
+    V11,
+}
+
+impl RoomVersion {
+    /// The version by its identifier in `m.room.create`, such as `"10"`.
+    pub fn from_id(id: &str) -> Option<Self> {
+        Some(match id {
+            "1" => RoomVersion::V1,
+            "2" => RoomVersion::V2,
+            "3" => RoomVersion::V3,
+            "4" => RoomVersion::V4,
+            "5" => RoomVersion::V5,
+            "6" => RoomVersion::V6,
+            "7" => RoomVersion::V7,
+            "8" => RoomVersion::V8,
+            "9" => RoomVersion::V9,
+            "10" => RoomVersion::V10,
+            "11" => RoomVersion::V11,
+            _ => return None,
+        })
+    }
+
+    // Top-level members of an event that survive redaction.
+    fn keeps(self, key: &str) -> bool {
+        match key {
+            "event_id" | "type" | "room_id" | "sender" | "state_key" | "content" | "hashes"
+            | "signatures" | "depth" | "prev_events" | "auth_events" | "origin_server_ts" => true,
+            // dropped by MSC2176 and MSC3989
+            "origin" | "membership" | "prev_state" => self < RoomVersion::V11,
+            _ => false,
+        }
+    }
+
+    // Members of `content` that survive redaction for events of `kind`.
+    fn keeps_content(self, kind: &str, key: &str) -> bool {
+        match (kind, key) {
+            ("m.room.member", "membership") => true,
+            ("m.room.member", "join_authorised_via_users_server") => self >= RoomVersion::V9,
+            ("m.room.create", "creator") => true,
+            ("m.room.create", _) => self >= RoomVersion::V11,
+            ("m.room.join_rules", "join_rule") => true,
+            ("m.room.join_rules", "allow") => self >= RoomVersion::V8,
+            ("m.room.power_levels", "ban")
+            | ("m.room.power_levels", "events")
+            | ("m.room.power_levels", "events_default")
+            | ("m.room.power_levels", "kick")
+            | ("m.room.power_levels", "redact")
+            | ("m.room.power_levels", "state_default")
+            | ("m.room.power_levels", "users")
+            | ("m.room.power_levels", "users_default") => true,
+            ("m.room.power_levels", "invite") => self >= RoomVersion::V11,
+            ("m.room.history_visibility", "history_visibility") => true,
+            ("m.room.aliases", "aliases") => self <= RoomVersion::V5,
+            ("m.room.redaction", "redacts") => self >= RoomVersion::V11,
+            _ => false,
+        }
+    }
+}
+
+/// The redacted form of `event` under the rules of `version`: every
+/// top-level member and every member of `content` that the version does not
+/// protect is removed.
+pub fn redact(event: &Value, version: RoomVersion) -> Result<Value> {
+    let event = as_object(event)?;
+    let kind = event.get("type").and_then(Value::as_str).unwrap_or("");
+
+    let mut redacted: Map<String, Value> = event
+        .iter()
+        .filter(|&(key, _)| version.keeps(key))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+
+    let mut content = Map::new();
+    if let Some(Value::Object(old)) = event.get("content") {
+        for (key, value) in old {
+            if version.keeps_content(kind, key) {
+                content.insert(key.clone(), value.clone());
+            }
+        }
+        // only the signed part of a third-party invite is kept (MSC3821)
+        if kind == "m.room.member" && version >= RoomVersion::V11 {
+            if let Some(signed) = old.get("third_party_invite").and_then(|i| i.get("signed")) {
+                let mut invite = Map::new();
+                invite.insert("signed".to_string(), signed.clone());
+                content.insert("third_party_invite".to_string(), Value::Object(invite));
+            }
+        }
+    }
+    redacted.insert("content".to_string(), Value::Object(content));
+
+    Ok(Value::Object(redacted))
+}
+
+// Unpadded standard base64, as Matrix writes it. Padded input is accepted.
+#[cfg(feature = "matrix")]
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new()
+        .with_encode_padding(false)
+        .with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// SHA-256 of the canonical form of `event` without its `signatures`,
+/// `unsigned` and `hashes` members.
+#[cfg(feature = "matrix")]
+pub fn content_hash(event: &Value) -> Result<Output<Sha256>> {
+    let mut event = as_object(event)?.clone();
+    event.remove("signatures");
+    event.remove("unsigned");
+    event.remove("hashes");
+    let mut ser = serializer(HashWriter::<Sha256>::new());
+    event.serialize(&mut ser)?;
+    Ok(ser.into_inner().finalize())
+}
+
+/// Sets `hashes.sha256` of `event` to its content hash.
+#[cfg(feature = "matrix")]
+pub fn add_content_hash(event: &mut Value) -> Result<()> {
+    let hash = BASE64.encode(content_hash(event)?);
+    let event = as_object_mut(event)?;
+    let mut hashes = Map::new();
+    hashes.insert("sha256".to_string(), Value::String(hash));
+    event.insert("hashes".to_string(), Value::Object(hashes));
+    Ok(())
+}
+
+/// Checks `hashes.sha256` of `event` against its content. Servers redact
+/// events that fail this check rather than dropping them.
+#[cfg(feature = "matrix")]
+pub fn verify_content_hash(event: &Value) -> Result<()> {
+    let mismatch = || Error::Verify(ErrorKind::ContentHashMismatch);
+    let expected = event
+        .pointer("/hashes/sha256")
+        .and_then(Value::as_str)
+        .and_then(|hash| BASE64.decode(hash).ok())
+        .ok_or_else(mismatch)?;
+    if !super::hash::constant_time_eq(&content_hash(event)?, &expected) {
+        return Err(mismatch());
+    }
+    Ok(())
+}
+
+/// Signs `object` on behalf of `entity`, a server name or user ID, and adds
+/// the signature under `signatures.<entity>.<key_id>`, where `key_id` is
+/// such as `ed25519:auto`. Other signatures and `unsigned` are kept.
+#[cfg(feature = "matrix")]
+pub fn sign_json(object: &mut Value, entity: &str, key_id: &str, key: &SigningKey) -> Result<()> {
+    let signature = BASE64.encode(key.sign(&signable_bytes(object)?).to_bytes());
+    let signatures = as_object_mut(object)?
+        .entry("signatures")
+        .or_insert_with(|| Value::Object(Map::new()));
+    let signatures = as_object_mut(signatures)?
+        .entry(entity)
+        .or_insert_with(|| Value::Object(Map::new()));
+    as_object_mut(signatures)?.insert(key_id.to_string(), Value::String(signature));
+    Ok(())
+}
+
+/// Checks the signature of `entity` with `key_id` on `object`.
+#[cfg(feature = "matrix")]
+pub fn verify_json(object: &Value, entity: &str, key_id: &str, key: &VerifyingKey) -> Result<()> {
+    let bad = || Error::Verify(ErrorKind::BadSignature);
+    let signature = as_object(object)?
+        .get("signatures")
+        .and_then(|signatures| signatures.get(entity))
+        .and_then(|signatures| signatures.get(key_id))
+        .and_then(Value::as_str)
+        .and_then(|signature| BASE64.decode(signature).ok())
+        .and_then(|signature| Signature::from_slice(&signature).ok())
+        .ok_or_else(bad)?;
+    key.verify(&signable_bytes(object)?, &signature)
+        .map_err(|_| bad())
+}
+
+/// Adds the content hash of `event`, then signs its redacted form and
+/// copies the resulting signatures back onto `event`.
+#[cfg(feature = "matrix")]
+pub fn sign_event(
+    event: &mut Value,
+    version: RoomVersion,
+    entity: &str,
+    key_id: &str,
+    key: &SigningKey,
+) -> Result<()> {
+    add_content_hash(event)?;
+    let mut redacted = redact(event, version)?;
+    sign_json(&mut redacted, entity, key_id, key)?;
+    let signatures = as_object_mut(&mut redacted)?
+        .remove("signatures")
+        .unwrap_or_default();
+    as_object_mut(event)?.insert("signatures".to_string(), signatures);
+    Ok(())
+}
+
+/// Checks the signature of `entity` with `key_id` on the redacted form of
+/// `event`. The content hash is checked separately, by
+/// `verify_content_hash`.
+#[cfg(feature = "matrix")]
+pub fn verify_event(
+    event: &Value,
+    version: RoomVersion,
+    entity: &str,
+    key_id: &str,
+    key: &VerifyingKey,
+) -> Result<()> {
+    verify_json(&redact(event, version)?, entity, key_id, key)
+}
@@ -0,0 +1,376 @@
+//! JSON Patch (RFC 6902) and JSON Merge Patch (RFC 7386) for canonical
+//! documents.
+//!
+//! `CanonicalValue::apply_patch` applies a whole `Patch` or nothing, and
+//! fails when the result has no canonical form, such as when an operation
+//! adds a float. `CanonicalValue::diff` goes the other way. The patches it
+//! makes depend only on the two documents, so they can be canonicalized and
+//! hashed in turn.
+//!
+//! Merge patches are documents themselves, so `merge_patch` and
+//! `merge_patch_diff` in `canonical_value` take and give `CanonicalValue`s.
+
+use super::canonical_value::{locate, missing, pointer_tokens, to_value};
+use super::error::{Error, ErrorKind, Result};
+use super::validate::escape_token;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeSet;
+
+/// A JSON Patch: operations applied one after another.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct Patch(pub Vec<Operation>);
+
+/// One operation of a JSON Patch. Paths are JSON Pointers.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Operation {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value },
+}
+
+/// Applies the operations of `patch` to `doc` in order, stopping at the
+/// first that fails. Callers keep a copy to go back to.
+pub(crate) fn apply(doc: &mut Value, patch: &Patch) -> Result<()> {
+    patch
+        .0
+        .iter()
+        .try_for_each(|operation| apply_operation(doc, operation))
+}
+
+/// Applies a single operation to `doc`.
+pub(crate) fn apply_operation(doc: &mut Value, operation: &Operation) -> Result<()> {
+    match *operation {
+        Operation::Add {
+            ref path,
+            ref value,
+        } => add(doc, path, value.clone())?,
+        Operation::Remove { ref path } => {
+            remove(doc, path)?;
+        }
+        Operation::Replace {
+            ref path,
+            ref value,
+        } => *get_mut(doc, path)? = value.clone(),
+        Operation::Move { ref from, ref path } => {
+            if path.starts_with(from.as_str()) && path[from.len()..].starts_with('/') {
+                return Err(locate(
+                    Error::canonical(ErrorKind::InvalidPatch, path),
+                    &pointer_tokens(from)?,
+                ));
+            }
+            let value = remove(doc, from)?;
+            add(doc, path, value)?;
+        }
+        Operation::Copy { ref from, ref path } => {
+            let value = get_mut(doc, from)?.clone();
+            add(doc, path, value)?;
+        }
+        Operation::Test {
+            ref path,
+            ref value,
+        } => {
+            // numbers compare by value, so `1.0` matches `1`; a value
+            // that still has a float cannot match a canonical document
+            let matches = match to_value(value) {
+                Ok(value) => get_mut(doc, path)? == value.as_value(),
+                Err(_) => false,
+            };
+            if !matches {
+                let err = Error::canonical(ErrorKind::TestFailed, value);
+                return Err(locate(err, &pointer_tokens(path)?));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn get_mut<'a>(doc: &'a mut Value, path: &str) -> Result<&'a mut Value> {
+    pointer_tokens(path)?;
+    doc.pointer_mut(path).ok_or_else(|| missing(path))
+}
+
+// The pointer to the parent of the node at `path`, which has tokens.
+fn parent(path: &str) -> &str {
+    &path[..path.rfind('/').unwrap_or(0)]
+}
+
+/// Adds `value` at `path`: a member is added or replaced, an element is
+/// inserted before the one at its index, and `-` appends.
+pub(crate) fn add(doc: &mut Value, path: &str, value: Value) -> Result<()> {
+    let last = match pointer_tokens(path)?.pop() {
+        Some(last) => last,
+        None => {
+            *doc = value;
+            return Ok(());
+        }
+    };
+    match doc.pointer_mut(parent(path)) {
+        Some(Value::Object(object)) => {
+            object.insert(last, value);
+        }
+        Some(Value::Array(array)) if last == "-" => array.push(value),
+        Some(Value::Array(array)) => {
+            let i = array_index(&last, array.len() + 1).ok_or_else(|| missing(path))?;
+            array.insert(i, value);
+        }
+        _ => return Err(missing(path)),
+    }
+    Ok(())
+}
+
+/// Takes the node at `path` out of `doc`. Elements after a removed array
+/// element move down. The whole document cannot be removed.
+pub(crate) fn remove(doc: &mut Value, path: &str) -> Result<Value> {
+    let last = match pointer_tokens(path)?.pop() {
+        Some(last) => last,
+        None => return Err(Error::canonical(ErrorKind::InvalidPointer, path)),
+    };
+    let old = match doc.pointer_mut(parent(path)) {
+        Some(Value::Object(object)) => object.remove(&last),
+        Some(Value::Array(array)) => array_index(&last, array.len()).map(|i| array.remove(i)),
+        _ => None,
+    };
+    old.ok_or_else(|| missing(path))
+}
+
+/// An array index as RFC 6901 writes it, without leading zeros, below `len`.
+pub(crate) fn array_index(token: &str, len: usize) -> Option<usize> {
+    if token.is_empty()
+        || (token.len() > 1 && token.starts_with('0'))
+        || !token.bytes().all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+    token.parse().ok().filter(|&i| i < len)
+}
+
+/// A patch that turns `old` into `new`, as `CanonicalValue::diff` describes.
+pub(crate) fn diff(old: &Value, new: &Value) -> Patch {
+    let mut operations = Vec::new();
+    diff_at(&mut String::new(), old, new, &mut operations);
+    Patch(operations)
+}
+
+fn diff_at(path: &mut String, old: &Value, new: &Value, operations: &mut Vec<Operation>) {
+    if old == new {
+        return;
+    }
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => diff_objects(path, old, new, operations),
+        (Value::Array(old), Value::Array(new)) => diff_arrays(path, old, new, operations),
+        _ => operations.push(Operation::Replace {
+            path: path.clone(),
+            value: new.clone(),
+        }),
+    }
+}
+
+fn diff_objects(
+    path: &mut String,
+    old: &Map<String, Value>,
+    new: &Map<String, Value>,
+    operations: &mut Vec<Operation>,
+) {
+    // `Map` keeps insertion order when serde_json has `preserve_order` on
+    let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    for key in keys {
+        let len = path.len();
+        path.push('/');
+        path.push_str(&escape_token(key));
+        match (old.get(key), new.get(key)) {
+            (Some(old), Some(new)) => diff_at(path, old, new, operations),
+            (Some(_), None) => operations.push(Operation::Remove { path: path.clone() }),
+            (None, Some(new)) => operations.push(Operation::Add {
+                path: path.clone(),
+                value: new.clone(),
+            }),
+            (None, None) => unreachable!(),
+        }
+        path.truncate(len);
+    }
+}
+
+// Arrays larger than this, in element pairs left after trimming the common
+// prefix and suffix, are not aligned, so that a diff never needs more than a
+// few megabytes.
+const MAX_ALIGNMENT: usize = 1 << 20;
+
+fn diff_arrays(path: &mut String, old: &[Value], new: &[Value], operations: &mut Vec<Operation>) {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old = &old[prefix..old.len() - suffix];
+    let new = &new[prefix..new.len() - suffix];
+
+    // Walks the old elements front to back. `at` is where the next one is in
+    // the array as the operations so far have left it, which is also its
+    // index in `new` once every operation before it has been applied.
+    let mut at = prefix;
+    let element = |path: &String, at: usize| format!("{}/{}", path, at);
+    for step in align(old, new) {
+        match step {
+            Step::Keep => at += 1,
+            Step::Change(i, j) => {
+                diff_at(&mut element(path, at), &old[i], &new[j], operations);
+                at += 1;
+            }
+            Step::Remove => operations.push(Operation::Remove {
+                path: element(path, at),
+            }),
+            Step::Add(j) => {
+                operations.push(Operation::Add {
+                    path: element(path, at),
+                    value: new[j].clone(),
+                });
+                at += 1;
+            }
+        }
+    }
+}
+
+enum Step {
+    Keep,
+    Change(usize, usize),
+    Remove,
+    Add(usize),
+}
+
+// The fewest element changes, removals and additions that turn `old` into
+// `new` (their edit distance), in order from the front. Ties go to a change,
+// then a removal, then an addition. Too large an input is instead aligned
+// position by position, with the rest removed or added at the end.
+fn align(old: &[Value], new: &[Value]) -> Vec<Step> {
+    let (n, m) = (old.len(), new.len());
+    if (n + 1).saturating_mul(m + 1) > MAX_ALIGNMENT {
+        let common = n.min(m);
+        let mut steps: Vec<Step> = (0..common)
+            .map(|i| {
+                if old[i] == new[i] {
+                    Step::Keep
+                } else {
+                    Step::Change(i, i)
+                }
+            })
+            .collect();
+        steps.extend((common..n).map(|_| Step::Remove));
+        steps.extend((common..m).map(Step::Add));
+        return steps;
+    }
+
+    // cost[i][j]: operations that turn old[i..] into new[j..]
+    let width = m + 1;
+    let mut cost = vec![0u32; (n + 1) * width];
+    for i in (0..=n).rev() {
+        for j in (0..=m).rev() {
+            cost[i * width + j] = if i == n {
+                (m - j) as u32
+            } else if j == m {
+                (n - i) as u32
+            } else if old[i] == new[j] {
+                cost[(i + 1) * width + j + 1]
+            } else {
+                1 + cost[(i + 1) * width + j + 1]
+                    .min(cost[(i + 1) * width + j])
+                    .min(cost[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut steps = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        let here = cost[i * width + j];
+        if i < n && j < m && old[i] == new[j] {
+            steps.push(Step::Keep);
+            i += 1;
+            j += 1;
+        } else if i < n && j < m && here == 1 + cost[(i + 1) * width + j + 1] {
+            steps.push(Step::Change(i, j));
+            i += 1;
+            j += 1;
+        } else if i < n && here == 1 + cost[(i + 1) * width + j] {
+            steps.push(Step::Remove);
+            i += 1;
+        } else {
+            steps.push(Step::Add(j));
+            j += 1;
+        }
+    }
+    steps
+}
+
+/// Merges `patch` into `target` as RFC 7386 describes: an object patch sets
+/// or, with `null`, deletes members, and anything else replaces `target`.
+pub(crate) fn merge(target: &mut Value, patch: &Value) {
+    let patch = match patch {
+        Value::Object(patch) => patch,
+        _ => {
+            *target = patch.clone();
+            return;
+        }
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    if let Value::Object(target) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(key);
+            } else {
+                merge(target.entry(key.as_str()).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+/// The smallest merge patch that turns `old` into `new`. Fails with
+/// `ErrorKind::MergePatchNull` when `new` has a `null` member that `old`
+/// does not, since no merge patch can add one.
+pub(crate) fn merge_diff(old: &Value, new: &Value) -> Result<Value> {
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            let mut patch = Map::new();
+            let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+            for key in keys {
+                let value = match (old.get(key), new.get(key)) {
+                    (Some(old), Some(new)) if old == new => continue,
+                    (Some(_), None) => Value::Null,
+                    (_, Some(Value::Null)) => {
+                        return Err(Error::canonical(ErrorKind::MergePatchNull, key).at(key))
+                    }
+                    (Some(old), Some(new)) => merge_diff(old, new).map_err(|err| err.at(key))?,
+                    (None, Some(new)) => added(new).map_err(|err| err.at(key))?,
+                    (None, None) => unreachable!(),
+                };
+                patch.insert(key.clone(), value);
+            }
+            Ok(Value::Object(patch))
+        }
+        (_, Value::Object(_)) => added(new),
+        _ => Ok(new.clone()),
+    }
+}
+
+// A value merged where there is no object yet loses its `null` members, so
+// it must not have any.
+fn added(value: &Value) -> Result<Value> {
+    if let Value::Object(object) = value {
+        for (key, value) in object {
+            if value.is_null() {
+                return Err(Error::canonical(ErrorKind::MergePatchNull, key).at(key));
+            }
+            added(value).map_err(|err| err.at(key))?;
+        }
+    }
+    Ok(value.clone())
+}
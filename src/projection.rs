@@ -0,0 +1,186 @@
+//! Leaving parts of a value out of its canonical form.
+//!
+//! A `Projection` is a list of JSON Pointers (RFC 6901) that either names the
+//! only parts to keep or the parts to drop, such as `/signatures` before
+//! hashing a signed object. It is applied by the serializer as it goes, so the
+//! value is never copied. A `*` token matches every element of an array; on
+//! an object it is an ordinary key.
+//!
+//! Pointers given to `include` and `exclude` must each match something, and
+//! serialization fails with `ErrorKind::MissingPath` when one does not. A
+//! pointer through `*` must match below every element of the array. Those
+//! given to `optional` may match nothing.
+
+use super::error::{Error, ErrorKind, Result};
+use super::validate::parse_pointer;
+
+/// Which parts of a value to serialize.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Projection {
+    mode: Mode,
+    paths: Vec<Path>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Mode {
+    Include,
+    Exclude,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Path {
+    pointer: String,
+    tokens: Vec<String>,
+    required: bool,
+}
+
+impl Projection {
+    /// Keeps the nodes at `pointers` with everything below them, and the
+    /// objects and arrays on the way to them. Everything else is left out.
+    pub fn include<I>(pointers: I) -> Result<Self>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        Projection::with_mode(Mode::Include).add(pointers, true)
+    }
+
+    /// Leaves out the nodes at `pointers`. Elements left out of an array
+    /// shift the ones after them.
+    pub fn exclude<I>(pointers: I) -> Result<Self>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        Projection::with_mode(Mode::Exclude).add(pointers, true)
+    }
+
+    /// Adds pointers that are allowed to match nothing.
+    pub fn optional<I>(self, pointers: I) -> Result<Self>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.add(pointers, false)
+    }
+
+    fn with_mode(mode: Mode) -> Self {
+        Projection {
+            mode,
+            paths: Vec::new(),
+        }
+    }
+
+    // The whole document cannot be projected, so the empty pointer is
+    // rejected along with malformed ones.
+    fn add<I>(mut self, pointers: I, required: bool) -> Result<Self>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        for pointer in pointers {
+            let pointer = pointer.as_ref();
+            let tokens = parse_pointer(pointer)
+                .filter(|tokens| !tokens.is_empty())
+                .ok_or_else(|| Error::canonical(ErrorKind::InvalidPointer, pointer))?;
+            self.paths.push(Path {
+                pointer: pointer.to_string(),
+                tokens,
+                required,
+            });
+        }
+        Ok(self)
+    }
+
+    /// Where serialization starts: every path passes through the root.
+    pub(crate) fn root(&self) -> Cursor {
+        Cursor {
+            active: (0..self.paths.len())
+                .map(|path| Active {
+                    path,
+                    next: 0,
+                    seen: false,
+                })
+                .collect(),
+            included: false,
+        }
+    }
+
+    /// Moves from a node to its member or element `token`. Returns the
+    /// cursor of the child, or `None` when the child is left out.
+    pub(crate) fn step(&self, cursor: &mut Cursor, token: &str, in_array: bool) -> Option<Cursor> {
+        let mut child = Cursor::default();
+        let mut complete = false;
+        for active in &mut cursor.active {
+            let tokens = &self.paths[active.path].tokens;
+            let expected = &tokens[active.next];
+            if expected == token || (in_array && expected == "*") {
+                active.seen = true;
+                if active.next + 1 == tokens.len() {
+                    complete = true;
+                } else {
+                    child.active.push(Active {
+                        path: active.path,
+                        next: active.next + 1,
+                        seen: false,
+                    });
+                }
+            }
+        }
+
+        match self.mode {
+            Mode::Include if cursor.included || complete => {
+                child.included = true;
+                Some(child)
+            }
+            Mode::Include if child.active.is_empty() => None,
+            Mode::Exclude if complete => None,
+            Mode::Include | Mode::Exclude => Some(child),
+        }
+    }
+
+    /// Called for a scalar: any required path going further is missing.
+    pub(crate) fn check_leaf(&self, cursor: &Cursor) -> Result<()> {
+        match cursor.active.iter().find(|a| self.paths[a.path].required) {
+            Some(active) => Err(self.missing(active)),
+            None => Ok(()),
+        }
+    }
+
+    /// Called at the end of an array or object: any required path whose
+    /// next token matched none of its children is missing. `*` is always
+    /// satisfied by an array, even an empty one.
+    pub(crate) fn check_end(&self, cursor: &Cursor, is_array: bool) -> Result<()> {
+        let missing = cursor.active.iter().find(|a| {
+            let path = &self.paths[a.path];
+            path.required && !a.seen && !(is_array && path.tokens[a.next] == "*")
+        });
+        match missing {
+            Some(active) => Err(self.missing(active)),
+            None => Ok(()),
+        }
+    }
+
+    fn missing(&self, active: &Active) -> Error {
+        let path = &self.paths[active.path];
+        Error::canonical(ErrorKind::MissingPath, &path.pointer).at(&path.tokens[active.next])
+    }
+}
+
+/// The paths of a projection that go through one node of the document.
+#[derive(Debug, Default)]
+#[doc(hidden)]
+pub struct Cursor {
+    active: Vec<Active>,
+    // the node is kept with everything below it
+    included: bool,
+}
+
+#[derive(Debug)]
+struct Active {
+    path: usize,
+    // position of the token a child has to match
+    next: usize,
+    // whether a child matched it
+    seen: bool,
+}
@@ -0,0 +1,105 @@
+//! Output byte-identical to securesystemslib's `encode_canonical`, which
+//! in-toto and the legacy Python TUF implementation sign over.
+//!
+//! The encoding is OLPC-style canonical JSON, so this is `ser` with the rules
+//! securesystemslib applies on top: every float is rejected, even one with an
+//! integral value that `ser` would write as an integer, and map keys must be
+//! strings. Control characters and non-ASCII text are written as-is and only
+//! `"` and `\` are escaped, as in `ser`.
+//!
+//! `key_id` and `key_ids` compute key IDs the way securesystemslib does for
+//! keys in in-toto layouts and legacy TUF metadata.
+
+use super::error::Result;
+use super::ser::{self, FloatPolicy, Serializer};
+use serde::Serialize;
+use std::io;
+
+#[cfg(feature = "sha2")]
+use super::{
+    hash::{Digest, HashWriter, Sha256, Sha512},
+    hex,
+    tuf::PublicKey,
+};
+
+pub fn to_writer<W, T>(writer: W, value: &T) -> Result<()>
+where
+    T: Serialize,
+    T: ?Sized,
+    W: io::Write,
+{
+    ser::write_rendered(writer, serializer(Vec::new()), value)
+}
+
+pub fn to_vec<T>(value: &T) -> Result<Vec<u8>>
+where
+    T: Serialize,
+    T: ?Sized,
+{
+    ser::render(serializer(Vec::with_capacity(128)), value)
+}
+
+pub fn to_string<T>(value: &T) -> Result<String>
+where
+    T: Serialize,
+    T: ?Sized,
+{
+    Ok(unsafe { String::from_utf8_unchecked(to_vec(value)?) })
+}
+
+/// A `Serializer` configured like securesystemslib.
+pub fn serializer<W: io::Write>(writer: W) -> Serializer<W> {
+    Serializer::new(writer).float_policy(FloatPolicy::Reject)
+}
+
+/// The `keyid_hash_algorithms` securesystemslib adds to every key before
+/// hashing it (`securesystemslib.settings.HASH_ALGORITHMS`).
+pub const KEYID_HASH_ALGORITHMS: [&str; 2] = ["sha256", "sha512"];
+
+// The form of a public key securesystemslib hashes for its key ID, built by
+// `format_keyval_to_metadata`. Any other member of the key is left out.
+#[cfg(feature = "sha2")]
+#[derive(serde_derive::Serialize)]
+struct KeyMetadata<'a> {
+    keytype: &'a str,
+    scheme: &'a str,
+    keyid_hash_algorithms: [&'static str; 2],
+    keyval: KeyValue<'a>,
+}
+
+#[cfg(feature = "sha2")]
+#[derive(serde_derive::Serialize)]
+struct KeyValue<'a> {
+    public: &'a str,
+}
+
+#[cfg(feature = "sha2")]
+fn hex_digest<D: Digest>(key: &PublicKey) -> Result<String> {
+    let metadata = KeyMetadata {
+        keytype: &key.keytype,
+        scheme: &key.scheme,
+        keyid_hash_algorithms: KEYID_HASH_ALGORITHMS,
+        keyval: KeyValue {
+            public: &key.keyval.public,
+        },
+    };
+    let mut ser = serializer(HashWriter::<D>::new());
+    metadata.serialize(&mut ser)?;
+    Ok(hex::encode(&ser.into_inner().finalize()))
+}
+
+/// The key ID securesystemslib assigns to `key`: the hex SHA-256 of its
+/// canonical form with `keyid_hash_algorithms` set to
+/// `KEYID_HASH_ALGORITHMS`. This differs from `PublicKey::key_id`, which
+/// hashes the key exactly as given.
+#[cfg(feature = "sha2")]
+pub fn key_id(key: &PublicKey) -> Result<String> {
+    hex_digest::<Sha256>(key)
+}
+
+/// Every ID securesystemslib accepts for `key`, one per algorithm of
+/// `KEYID_HASH_ALGORITHMS`, in that order.
+#[cfg(feature = "sha2")]
+pub fn key_ids(key: &PublicKey) -> Result<Vec<String>> {
+    Ok(vec![hex_digest::<Sha256>(key)?, hex_digest::<Sha512>(key)?])
+}
@@ -1,27 +1,53 @@
-use super::error::{Error, Result};
+use super::error::{Error, ErrorKind, Result};
+use super::jcs;
+use super::limits::Limits;
+use super::projection::{Cursor, Projection};
 use itoa;
 use serde::ser::Impossible;
 use serde::{ser, Serialize};
-use std::{i64, io, num::FpCategory};
-
+use std::io::{self, Write};
+use std::{cmp::Ordering, convert::TryFrom, fmt, mem, num::FpCategory, ops::Range, sync::Arc};
+
+/// Writes the canonical form of `value` to `writer`, and nothing at all when
+/// `value` cannot be canonicalized.
+///
+/// The `Serializer` itself makes a single pass that only holds back the
+/// members of each object, to sort them, and writes everything else
+/// straight through. That alone cannot keep the promise above, since the
+/// elements of an array written so far cannot be taken back when a later
+/// one fails. So this renders the whole document into memory first and
+/// writes it in one go, at the price of holding the full output next to
+/// `value`. Members are also copied into the buffer of each object around
+/// them, once per level.
+///
+/// Where partial output on error is acceptable, serialize into
+/// `Serializer::new(writer)` instead, which holds no more than the largest
+/// object.
 pub fn to_writer<W, T>(writer: W, value: &T) -> Result<()>
 where
     T: serde::Serialize,
     T: ?Sized,
     W: io::Write,
 {
-    let mut ser = Serializer::new(writer);
-    super::canonical_value::to_value(value)?.serialize(&mut ser)?;
-    Ok(())
+    write_rendered(writer, Serializer::new(Vec::new()), value)
 }
 
 pub fn to_vec<T>(value: &T) -> Result<Vec<u8>>
+where
+    T: serde::Serialize,
+    T: ?Sized,
+{
+    render(Serializer::new(Vec::with_capacity(128)), value)
+}
+
+/// `to_vec` of only the parts of `value` that `projection` keeps.
+pub fn to_vec_with<T>(value: &T, projection: &Projection) -> Result<Vec<u8>>
 where
     T: serde::Serialize,
     T: ?Sized,
 {
     let mut writer = Vec::with_capacity(128);
-    to_writer(&mut writer, value)?;
+    value.serialize(&mut Serializer::new(&mut writer).projection(projection.clone()))?;
     Ok(writer)
 }
 
@@ -33,11 +59,133 @@ where
     Ok(unsafe { String::from_utf8_unchecked(to_vec(value)?) })
 }
 
+// The body of the `to_vec` functions here and in the scheme modules, which
+// only differ in how `ser` is configured.
+pub(crate) fn render<T>(mut ser: Serializer<Vec<u8>>, value: &T) -> Result<Vec<u8>>
+where
+    T: Serialize,
+    T: ?Sized,
+{
+    value.serialize(&mut ser)?;
+    Ok(ser.into_inner())
+}
+
+// The body of the `to_writer` functions. `writer` only sees the document
+// once it is complete, see `to_writer`.
+pub(crate) fn write_rendered<W, T>(mut writer: W, ser: Serializer<Vec<u8>>, value: &T) -> Result<()>
+where
+    T: Serialize,
+    T: ?Sized,
+    W: io::Write,
+{
+    writer.write_all(&render(ser, value)?)?;
+    Ok(())
+}
+
+// Largest magnitude up to which integers survive a round trip through an
+// IEEE 754 double (`Number.MAX_SAFE_INTEGER` in JavaScript).
+const MAX_SAFE_INTEGER: i128 = (1 << 53) - 1;
+
+/// The canonicalization rules a `Serializer` applies.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Scheme {
+    /// OLPC-style canonical JSON: only integral numbers, only `"` and `\`
+    /// escaped, object keys ordered by their UTF-8 bytes.
+    #[default]
+    Olpc,
+    /// RFC 8785 JSON Canonicalization Scheme: ECMAScript number formatting,
+    /// JSON escaping of control characters, object keys ordered by their
+    /// UTF-16 code units.
+    Jcs,
+    /// Matrix canonical JSON: object keys ordered by code point, JSON
+    /// escaping of control characters, non-ASCII text written as-is, floats
+    /// rejected and integers limited to ±(2^53 − 1).
+    Matrix,
+}
+
+impl Scheme {
+    fn cmp_keys(self, a: &str, b: &str) -> Ordering {
+        match self {
+            Scheme::Olpc | Scheme::Matrix => a.cmp(b),
+            Scheme::Jcs => a.encode_utf16().cmp(b.encode_utf16()),
+        }
+    }
+
+    fn write_str<W: io::Write>(self, writer: &mut W, v: &str) -> Result<()> {
+        match self {
+            Scheme::Olpc => escape_str(writer, v),
+            Scheme::Jcs | Scheme::Matrix => jcs::escape_str(writer, v),
+        }
+    }
+}
+
+/// What a `Serializer` does with `f32` and `f64` values. Non-finite values
+/// are rejected whatever the policy.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FloatPolicy {
+    /// Every float is rejected, even one with an integral value. The default
+    /// for `Scheme::Matrix`.
+    Reject,
+    /// Floats with an integral value are written as integers, anything with
+    /// a fractional part is rejected. The default for `Scheme::Olpc`.
+    Integral,
+    /// Floats are written with the ECMAScript shortest round-trip algorithm,
+    /// as RFC 8785 requires. The default for `Scheme::Jcs`.
+    Shortest,
+    /// Floats are rounded half to even to `precision` digits after the
+    /// decimal point and written as a JSON string, such as `"12.50"`.
+    DecimalString { precision: usize },
+}
+
+impl FloatPolicy {
+    fn for_scheme(scheme: Scheme) -> Self {
+        match scheme {
+            Scheme::Olpc => FloatPolicy::Integral,
+            Scheme::Jcs => FloatPolicy::Shortest,
+            Scheme::Matrix => FloatPolicy::Reject,
+        }
+    }
+}
+
+/// Writes canonical JSON in a single pass over the value. Object members are
+/// held back until their object ends so that they can be sorted; everything
+/// else goes straight to the writer. Unlike `to_writer`, using a `Serializer`
+/// directly can leave partial output behind when serialization fails.
 pub struct Serializer<W>
 where
     W: io::Write,
 {
-    writer: W,
+    writer: Counter<W>,
+    scheme: Scheme,
+    float_policy: FloatPolicy,
+    safe_integers: bool,
+    stringify_keys: bool,
+    // floats were read from JSON text by `serde_json`, see `parsed_floats`
+    parsed_floats: bool,
+    limits: Limits,
+    // arrays and objects currently open
+    depth: usize,
+    projection: Option<Arc<Projection>>,
+    // where the value about to be serialized sits relative to the projection
+    cursor: Cursor,
+}
+
+// Counts the bytes that pass through it, for `Limits::max_output_bytes`.
+struct Counter<W> {
+    inner: W,
+    count: usize,
+}
+
+impl<W: io::Write> io::Write for Counter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
 }
 
 impl<W> Serializer<W>
@@ -45,7 +193,210 @@ where
     W: io::Write,
 {
     pub fn new(writer: W) -> Self {
-        Serializer { writer: writer }
+        Serializer::with_scheme(writer, Scheme::Olpc)
+    }
+
+    pub fn with_scheme(writer: W, scheme: Scheme) -> Self {
+        Serializer {
+            writer: Counter {
+                inner: writer,
+                count: 0,
+            },
+            scheme,
+            float_policy: FloatPolicy::for_scheme(scheme),
+            safe_integers: scheme == Scheme::Matrix,
+            stringify_keys: false,
+            parsed_floats: false,
+            limits: Limits::default(),
+            depth: 0,
+            projection: None,
+            cursor: Cursor::default(),
+        }
+    }
+
+    /// Sets how floats are written, overriding the default of the scheme.
+    pub fn float_policy(mut self, policy: FloatPolicy) -> Self {
+        self.float_policy = policy;
+        self
+    }
+
+    /// Rejects integers outside ±(2^53 − 1), the range a consumer that
+    /// parses numbers as IEEE 754 doubles (such as JavaScript) reads back
+    /// exactly. Off by default except for `Scheme::Matrix`.
+    pub fn safe_integers(mut self, enabled: bool) -> Self {
+        self.safe_integers = enabled;
+        self
+    }
+
+    /// Accepts integer, `bool`, `char`, unit variant and newtype map keys and
+    /// writes them as strings, the way `serde_json` does. Objects are sorted
+    /// on the resulting strings, so `10` comes before `9`, and two keys that
+    /// stringify the same are a `DuplicateKey` error. Off by default, in
+    /// which case only string keys are accepted.
+    pub fn stringify_keys(mut self, enabled: bool) -> Self {
+        self.stringify_keys = enabled;
+        self
+    }
+
+    /// For floats that `serde_json` read from JSON text, which hands integer
+    /// literals that do not fit in 64 bits over as doubles, rounded. Under
+    /// `FloatPolicy::Integral` such doubles are refused as
+    /// `NumberOutOfRange` rather than written as a different integer.
+    pub(crate) fn parsed_floats(mut self, enabled: bool) -> Self {
+        self.parsed_floats = enabled;
+        self
+    }
+
+    /// Stops with an error once the output goes over one of `limits`. The
+    /// default only caps the depth.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Serializes only the parts of values that `projection` keeps.
+    pub fn projection(mut self, projection: Projection) -> Self {
+        self.cursor = projection.root();
+        self.projection = Some(Arc::new(projection));
+        self
+    }
+
+    /// Unwraps the writer, once serialization is done.
+    pub fn into_inner(self) -> W {
+        self.writer.inner
+    }
+
+    /// Serializer with the same rules as this one, writing into `buf`.
+    /// Used to render object members before they are sorted. It carries on
+    /// the depth and the byte count of this one, and the caller takes the
+    /// byte count back once the member is rendered.
+    fn nested<'b>(&self, buf: &'b mut Vec<u8>) -> Serializer<&'b mut Vec<u8>> {
+        Serializer {
+            writer: Counter {
+                inner: buf,
+                count: self.writer.count,
+            },
+            scheme: self.scheme,
+            float_policy: self.float_policy,
+            safe_integers: self.safe_integers,
+            stringify_keys: self.stringify_keys,
+            parsed_floats: self.parsed_floats,
+            limits: self.limits,
+            depth: self.depth,
+            projection: self.projection.clone(),
+            cursor: Cursor::default(),
+        }
+    }
+
+    // Called when an array or object is opened; `leave` when it is closed.
+    fn enter(&mut self) -> Result<()> {
+        self.depth += 1;
+        match self.limits.max_depth {
+            Some(max) if self.depth > max => Err(Error::canonical(ErrorKind::TooDeep, max)),
+            _ => Ok(()),
+        }
+    }
+
+    fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    // Checked after every scalar and at the end of every array and object,
+    // so the output never grows much past the limit before it is noticed.
+    fn check_output(&self) -> Result<()> {
+        match self.limits.max_output_bytes {
+            Some(max) if self.writer.count > max => {
+                Err(Error::canonical(ErrorKind::OutputTooLarge, max))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_string(&self, v: &str) -> Result<()> {
+        match self.limits.max_string_len {
+            Some(max) if v.len() > max => Err(Error::canonical(ErrorKind::StringTooLong, max)),
+            _ => Ok(()),
+        }
+    }
+
+    // Called for every scalar, which no projection path can go through.
+    fn check_leaf(&self) -> Result<()> {
+        match self.projection {
+            Some(ref projection) => projection.check_leaf(&self.cursor),
+            None => Ok(()),
+        }
+    }
+
+    // For a variant written as `{NAME: ...}`, moves from the outer object to
+    // its content. Returns the cursor of the content, or `None` when it is
+    // left out.
+    fn enter_variant(&mut self, variant: &'static str) -> Result<Option<Cursor>> {
+        match self.projection {
+            Some(ref projection) => {
+                let mut outer = mem::take(&mut self.cursor);
+                let content = projection.step(&mut outer, variant, false);
+                projection.check_end(&outer, false)?;
+                Ok(content)
+            }
+            None => Ok(Some(Cursor::default())),
+        }
+    }
+
+    fn check_safe_integer(&self, v: i128) -> Result<()> {
+        if self.safe_integers && !(-MAX_SAFE_INTEGER..=MAX_SAFE_INTEGER).contains(&v) {
+            return Err(Error::canonical(ErrorKind::NumberOutOfRange, v));
+        }
+        Ok(())
+    }
+
+    fn serialize_integer<I>(&mut self, v: I) -> Result<()>
+    where
+        I: itoa::Integer + Into<i128> + Copy,
+    {
+        self.check_leaf()?;
+        self.check_safe_integer(v.into())?;
+        match self.scheme {
+            Scheme::Olpc | Scheme::Matrix => {
+                itoa::write(&mut self.writer, v).map_err(Error::Io)?;
+            }
+            Scheme::Jcs => jcs::write_integer(&mut self.writer, v.into())?,
+        }
+        self.check_output()
+    }
+
+    fn serialize_float(&mut self, v: f64) -> Result<()> {
+        self.check_leaf()?;
+        self.write_float(v)?;
+        self.check_output()
+    }
+
+    fn write_float(&mut self, v: f64) -> Result<()> {
+        if let FpCategory::Nan | FpCategory::Infinite = v.classify() {
+            return Err(Error::canonical(ErrorKind::NonFinite, v));
+        }
+        match self.float_policy {
+            FloatPolicy::Reject => Err(Error::canonical(ErrorKind::Float, v)),
+            FloatPolicy::Integral => {
+                // `i128::MAX as f64` is 2^127, the first double out of range
+                if v.fract() != 0.0 || v < i128::MIN as f64 || v >= i128::MAX as f64 {
+                    return Err(Error::canonical(ErrorKind::Float, v));
+                }
+                // every integer literal in the 64-bit range comes as an integer
+                if self.parsed_floats && (v <= i64::MIN as f64 || v >= u64::MAX as f64) {
+                    return Err(Error::canonical(ErrorKind::NumberOutOfRange, v));
+                }
+                self.serialize_integer(v as i128)
+            }
+            FloatPolicy::Shortest => jcs::write_f64(&mut self.writer, v),
+            FloatPolicy::DecimalString { precision } => {
+                let mut s = format!("{:.*}", precision, v);
+                // values that round to zero are written without a sign
+                if s.starts_with('-') && s.bytes().all(|b| !matches!(b, b'1'..=b'9')) {
+                    s.remove(0);
+                }
+                self.scheme.write_str(&mut self.writer, &s)
+            }
+        }
     }
 }
 
@@ -65,119 +416,97 @@ where
     type SerializeStructVariant = OrderedKeyCompound<'a, W>;
 
     fn serialize_bool(self, v: bool) -> Result<()> {
+        self.check_leaf()?;
         let s = if v {
             b"true" as &[u8]
         } else {
             b"false" as &[u8]
         };
         self.writer.write_all(s).map_err(Error::Io)?;
-        Ok(())
+        self.check_output()
     }
 
     fn serialize_i8(self, v: i8) -> Result<()> {
-        itoa::write(&mut self.writer, v).map_err(Error::Io)?;
-        Ok(())
+        self.serialize_integer(v)
     }
 
     fn serialize_i16(self, v: i16) -> Result<()> {
-        itoa::write(&mut self.writer, v).map_err(Error::Io)?;
-        Ok(())
+        self.serialize_integer(v)
     }
 
     fn serialize_i32(self, v: i32) -> Result<()> {
-        itoa::write(&mut self.writer, v).map_err(Error::Io)?;
-        Ok(())
+        self.serialize_integer(v)
     }
 
     fn serialize_i64(self, v: i64) -> Result<()> {
-        itoa::write(&mut self.writer, v).map_err(Error::Io)?;
-        Ok(())
+        self.serialize_integer(v)
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<()> {
+        self.serialize_integer(v)
     }
 
-    // TODO - Radu M
-    // check the serde_if_integer128! macro for 128-bit integers
     fn serialize_u8(self, v: u8) -> Result<()> {
-        itoa::write(&mut self.writer, v).map_err(Error::Io)?;
-        Ok(())
+        self.serialize_integer(v)
     }
 
     fn serialize_u16(self, v: u16) -> Result<()> {
-        itoa::write(&mut self.writer, v).map_err(Error::Io)?;
-        Ok(())
+        self.serialize_integer(v)
     }
 
     fn serialize_u32(self, v: u32) -> Result<()> {
-        itoa::write(&mut self.writer, v).map_err(Error::Io)?;
-        Ok(())
+        self.serialize_integer(v)
     }
 
     fn serialize_u64(self, v: u64) -> Result<()> {
-        itoa::write(&mut self.writer, v).map_err(Error::Io)?;
-        Ok(())
+        self.serialize_integer(v)
     }
 
-    fn serialize_f32(self, v: f32) -> Result<()> {
-        match v.classify() {
-            FpCategory::Nan | FpCategory::Infinite => {
-                return Err(Error::Custom(String::from(format!(
-                    "value not allowed in cannonical JSON: {}",
-                    v
-                ))))
-            }
-            _ => {
-                if v.fract() != 0.0 {
-                    return Err(Error::Custom(String::from(format!(
-                        "value not allowed in cannonical JSON: {}",
-                        v
-                    ))));
-                }
-                if v != (v as i64) as f32 {
-                    return Err(Error::Custom(String::from(format!(
-                        "value not allowed in cannonical JSON: {}",
-                        v
-                    ))));
-                }
-                itoa::write(&mut self.writer, v as i64).map_err(Error::Io)?;
+    fn serialize_u128(self, v: u128) -> Result<()> {
+        if let Ok(v) = i128::try_from(v) {
+            return self.serialize_integer(v);
+        }
+        self.check_leaf()?;
+        // beyond `i128::MAX`, far outside the safe range
+        if self.safe_integers {
+            return Err(Error::canonical(ErrorKind::NumberOutOfRange, v));
+        }
+        match self.scheme {
+            Scheme::Olpc | Scheme::Matrix => {
+                itoa::write(&mut self.writer, v).map_err(Error::Io)?;
             }
+            Scheme::Jcs => jcs::write_u128(&mut self.writer, v)?,
         }
-        Ok(())
+        self.check_output()
     }
 
-    fn serialize_f64(self, v: f64) -> Result<()> {
-        match v.classify() {
-            FpCategory::Nan | FpCategory::Infinite => {
-                return Err(Error::Custom(String::from(format!(
-                    "value not allowed in cannonical JSON: {}",
-                    v
-                ))))
-            }
-            _ => {
-                if v.fract() != 0.0 {
-                    return Err(Error::Custom(String::from(format!(
-                        "value not allowed in cannonical JSON: {}",
-                        v
-                    ))));
-                }
-                if v != (v as i64) as f64 {
-                    return Err(Error::Custom(String::from(format!(
-                        "value not allowed in cannonical JSON: {}",
-                        v
-                    ))));
-                }
-                itoa::write(&mut self.writer, v as i64).map_err(Error::Io)?;
+    fn serialize_f32(self, v: f32) -> Result<()> {
+        // Decimal output works on doubles; widen through the shortest decimal
+        // form so that `0.1f32` is written as `0.1` rather than
+        // `0.10000000149011612`. Integral values are widened exactly.
+        let v = match self.float_policy {
+            FloatPolicy::Shortest | FloatPolicy::DecimalString { .. } if v.is_finite() => {
+                v.to_string().parse().unwrap_or_else(|_| f64::from(v))
             }
-        }
-        Ok(())
+            _ => f64::from(v),
+        };
+        self.serialize_float(v)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<()> {
+        self.serialize_float(v)
     }
 
     fn serialize_char(self, v: char) -> Result<()> {
-        escape_char(&mut self.writer, v)?;
-        Ok(())
+        let mut buf = [0; 4];
+        self.serialize_str(v.encode_utf8(&mut buf))
     }
 
     fn serialize_str(self, v: &str) -> Result<()> {
-        escape_str(&mut self.writer, v)?;
-        Ok(())
+        self.check_leaf()?;
+        self.check_string(v)?;
+        self.scheme.write_str(&mut self.writer, v)?;
+        self.check_output()
     }
 
     fn serialize_bytes(self, v: &[u8]) -> Result<()> {
@@ -202,8 +531,9 @@ where
     }
 
     fn serialize_unit(self) -> Result<()> {
+        self.check_leaf()?;
         self.writer.write_all(b"null")?;
-        Ok(())
+        self.check_output()
     }
 
     fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
@@ -238,29 +568,41 @@ where
     where
         T: ?Sized + Serialize,
     {
+        let content = self.enter_variant(variant)?;
+        self.enter()?;
         self.writer.write_all(b"{")?;
-        self.serialize_str(variant)?;
-        self.writer.write_all(b":")?;
-        value.serialize(&mut *self)?;
+        if let Some(cursor) = content {
+            self.serialize_str(variant)?;
+            self.writer.write_all(b":")?;
+            self.cursor = cursor;
+            value.serialize(&mut *self).map_err(|err| err.at(variant))?;
+        }
         self.writer.write_all(b"}")?;
+        self.leave();
 
-        Ok(())
+        self.check_output()
     }
 
     fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq> {
+        self.enter()?;
+        let cursor = mem::take(&mut self.cursor);
         if len == Some(0) {
             self.writer.write_all(b"[]")?;
-            Ok(OrderedKeyCompound::Map {
+            Ok(OrderedKeyCompound::Seq {
                 ser: self,
                 state: State::Empty,
-                cur_key: None,
+                index: 0,
+                variant: None,
+                cursor,
             })
         } else {
             self.writer.write_all(b"[")?;
-            Ok(OrderedKeyCompound::Map {
+            Ok(OrderedKeyCompound::Seq {
                 ser: self,
                 state: State::First,
-                cur_key: None,
+                index: 0,
+                variant: None,
+                cursor,
             })
         }
     }
@@ -284,28 +626,32 @@ where
         variant: &'static str,
         len: usize,
     ) -> Result<Self::SerializeTupleVariant> {
+        let content = self.enter_variant(variant)?;
+        self.enter()?;
         self.writer.write_all(b"{")?;
-        self.serialize_str(variant)?;
-        self.writer.write_all(b":")?;
-        self.serialize_seq(Some(len))
+        match content {
+            Some(cursor) => {
+                self.serialize_str(variant)?;
+                self.writer.write_all(b":")?;
+                self.cursor = cursor;
+                Ok(self.serialize_seq(Some(len))?.in_variant(variant))
+            }
+            None => Ok(OrderedKeyCompound::Skip { ser: self }),
+        }
     }
 
+    // Object members are buffered and only written, sorted, once the map
+    // ends, so the order in which they are serialized does not matter.
     fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap> {
-        if len == Some(0) {
-            self.writer.write_all(b"{}")?;
-            Ok(OrderedKeyCompound::Map {
-                ser: self,
-                state: State::Empty,
-                cur_key: None,
-            })
-        } else {
-            self.writer.write_all(b"{")?;
-            Ok(OrderedKeyCompound::Map {
-                ser: self,
-                state: State::First,
-                cur_key: None,
-            })
-        }
+        self.enter()?;
+        Ok(OrderedKeyCompound::Map {
+            cursor: mem::take(&mut self.cursor),
+            ser: self,
+            buf: Vec::new(),
+            entries: Vec::with_capacity(len.unwrap_or(0)),
+            cur_key: None,
+            variant: None,
+        })
     }
 
     fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeStruct> {
@@ -321,10 +667,18 @@ where
         variant: &'static str,
         len: usize,
     ) -> Result<Self::SerializeStructVariant> {
+        let content = self.enter_variant(variant)?;
+        self.enter()?;
         self.writer.write_all(b"{")?;
-        self.serialize_str(variant)?;
-        self.writer.write_all(b":")?;
-        self.serialize_map(Some(len))
+        match content {
+            Some(cursor) => {
+                self.serialize_str(variant)?;
+                self.writer.write_all(b":")?;
+                self.cursor = cursor;
+                Ok(self.serialize_map(Some(len))?.in_variant(variant))
+            }
+            None => Ok(OrderedKeyCompound::Skip { ser: self }),
+        }
     }
 }
 
@@ -337,16 +691,139 @@ pub enum State {
 }
 
 #[doc(hidden)]
-pub enum Compound<'a, W: 'a>
+pub enum OrderedKeyCompound<'a, W>
 where
     W: io::Write,
 {
-    Map {
+    Seq {
         ser: &'a mut Serializer<W>,
         state: State,
+        // position of the next element
+        index: usize,
+        // name of the variant when this is the content of a tuple variant
+        variant: Option<&'static str>,
+        cursor: Cursor,
+    },
+    Map {
+        ser: &'a mut Serializer<W>,
+        // rendered member values, back to back
+        buf: Vec<u8>,
+        // each key with the range of its value in `buf`
+        entries: Vec<(String, Range<usize>)>,
+        cur_key: Option<String>,
+        // name of the variant when this is the content of a struct variant
+        variant: Option<&'static str>,
+        cursor: Cursor,
+    },
+    // The content of a tuple or struct variant that a projection leaves out.
+    Skip {
+        ser: &'a mut Serializer<W>,
     },
 }
 
+// Errors raised inside an externally tagged variant sit below the variant's
+// name in the document.
+fn tag(err: Error, variant: Option<&'static str>) -> Error {
+    match variant {
+        Some(variant) => err.at(variant),
+        None => err,
+    }
+}
+
+impl<'a, W> OrderedKeyCompound<'a, W>
+where
+    W: io::Write,
+{
+    fn in_variant(mut self, name: &'static str) -> Self {
+        match self {
+            OrderedKeyCompound::Seq {
+                ref mut variant, ..
+            }
+            | OrderedKeyCompound::Map {
+                ref mut variant, ..
+            } => *variant = Some(name),
+            OrderedKeyCompound::Skip { .. } => {}
+        }
+        self
+    }
+
+    // Sorts the buffered members according to the serializer's scheme and
+    // writes them out. Returns the serializer so that variants can close
+    // their outer object.
+    fn end_map(self) -> Result<&'a mut Serializer<W>> {
+        match self {
+            OrderedKeyCompound::Map {
+                ser,
+                buf,
+                mut entries,
+                variant,
+                cursor,
+                ..
+            } => {
+                if let Some(ref projection) = ser.projection {
+                    projection
+                        .check_end(&cursor, false)
+                        .map_err(|err| tag(err, variant))?;
+                }
+
+                let scheme = ser.scheme;
+                entries.sort_by(|a, b| scheme.cmp_keys(&a.0, &b.0));
+                for pair in entries.windows(2) {
+                    if pair[0].0 == pair[1].0 {
+                        let err = Error::canonical(ErrorKind::DuplicateKey, &pair[0].0);
+                        return Err(tag(err.at(&pair[1].0), variant));
+                    }
+                }
+
+                // the members were counted as they were rendered
+                ser.writer.count -= buf.len();
+                ser.writer.write_all(b"{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        ser.writer.write_all(b",")?;
+                    }
+                    scheme.write_str(&mut ser.writer, key)?;
+                    ser.writer.write_all(b":")?;
+                    ser.writer.write_all(&buf[value.clone()])?;
+                }
+                ser.writer.write_all(b"}")?;
+                ser.leave();
+                ser.check_output()?;
+
+                Ok(ser)
+            }
+            OrderedKeyCompound::Skip { ser } => Ok(ser),
+            OrderedKeyCompound::Seq { .. } => unreachable!(),
+        }
+    }
+
+    fn end_seq(self) -> Result<&'a mut Serializer<W>> {
+        match self {
+            OrderedKeyCompound::Seq {
+                ser,
+                state,
+                variant,
+                cursor,
+                ..
+            } => {
+                if let Some(ref projection) = ser.projection {
+                    projection
+                        .check_end(&cursor, true)
+                        .map_err(|err| tag(err, variant))?;
+                }
+                if state != State::Empty {
+                    ser.writer.write_all(b"]")?;
+                }
+                ser.leave();
+                ser.check_output()?;
+                Ok(ser)
+            }
+            OrderedKeyCompound::Skip { ser } => Ok(ser),
+            OrderedKeyCompound::Map { .. } => unreachable!(),
+        }
+    }
+}
+
 impl<'a, W> ser::SerializeSeq for OrderedKeyCompound<'a, W>
 where
     W: io::Write,
@@ -354,41 +831,49 @@ where
     type Ok = ();
     type Error = Error;
 
-    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<()>
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         match *self {
-            OrderedKeyCompound::Map {
+            OrderedKeyCompound::Seq {
                 ref mut ser,
                 ref mut state,
-                ..
+                ref mut index,
+                variant,
+                ref mut cursor,
             } => {
+                if let Some(ref projection) = ser.projection {
+                    match projection.step(cursor, &index.to_string(), true) {
+                        Some(child) => ser.cursor = child,
+                        None => {
+                            *index += 1;
+                            return Ok(());
+                        }
+                    }
+                }
+
                 // begin array value
                 // if the value is not thre first, write a ","
-                match *state {
-                    State::Rest => ser.writer.write_all(b",")?,
-                    _ => (),
+                if *state == State::Rest {
+                    ser.writer.write_all(b",")?;
                 }
                 *state = State::Rest;
-                value.serialize(&mut **ser)?;
+                value
+                    .serialize(&mut **ser)
+                    .map_err(|err| tag(err.at(*index), variant))?;
+                *index += 1;
 
                 Ok(())
             }
+            OrderedKeyCompound::Skip { .. } => Ok(()),
+            OrderedKeyCompound::Map { .. } => unreachable!(),
         }
     }
 
     fn end(self) -> Result<()> {
-        match self {
-            OrderedKeyCompound::Map { ser, state, .. } => {
-                match state {
-                    State::Empty => {}
-                    _ => ser.writer.write_all(b"]")?,
-                }
-
-                Ok(())
-            }
-        }
+        self.end_seq()?;
+        Ok(())
     }
 }
 
@@ -399,9 +884,9 @@ where
     type Ok = ();
     type Error = Error;
 
-    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<()>
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         ser::SerializeSeq::serialize_element(self, value)
     }
@@ -418,9 +903,9 @@ where
     type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(&mut self, value: &T) -> Result<()>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         ser::SerializeSeq::serialize_element(self, value)
     }
@@ -437,40 +922,21 @@ where
     type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(&mut self, value: &T) -> Result<()>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         ser::SerializeSeq::serialize_element(self, value)
     }
 
     fn end(self) -> Result<()> {
-        match self {
-            OrderedKeyCompound::Map { ser, state, .. } => {
-                match state {
-                    State::Empty => {}
-                    _ => ser.writer.write_all(b"]")?,
-                }
-
-                ser.writer.write_all(b"}")?;
-                Ok(())
-            }
-        }
+        let ser = self.end_seq()?;
+        ser.writer.write_all(b"}")?;
+        ser.leave();
+        ser.check_output()
     }
 }
 
-#[doc(hidden)]
-pub enum OrderedKeyCompound<'a, W: 'a>
-where
-    W: io::Write,
-{
-    Map {
-        ser: &'a mut Serializer<W>,
-        cur_key: Option<String>,
-        state: State,
-    },
-}
-
 impl<'a, W> ser::SerializeMap for OrderedKeyCompound<'a, W>
 where
     W: io::Write,
@@ -478,60 +944,80 @@ where
     type Ok = ();
     type Error = Error;
 
-    fn serialize_key<T: ?Sized>(&mut self, key: &T) -> Result<()>
+    fn serialize_key<T>(&mut self, key: &T) -> Result<()>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         match *self {
             OrderedKeyCompound::Map {
-                ref mut ser,
+                ref ser,
+                ref entries,
                 ref mut cur_key,
-                ref mut state,
+                variant,
+                ..
             } => {
-                // begin object key
-                // if the value is not thre first, write a ","
-                match *state {
-                    State::Rest => ser.writer.write_all(b",")?,
-                    _ => (),
+                if let Some(max) = ser.limits.max_keys {
+                    if entries.len() >= max {
+                        let err = Error::canonical(ErrorKind::TooManyKeys, max);
+                        return Err(tag(err, variant));
+                    }
                 }
-                *state = State::Rest;
-
-                let mut key_serializer = AscendingKeySerializer {
-                    ser: ser,
-                    cur_key: cur_key.take(),
-                };
-
-                key.serialize(&mut key_serializer)?;
-
+                let key = key
+                    .serialize(MapKeySerializer {
+                        stringify: ser.stringify_keys,
+                    })
+                    .and_then(|key| ser.check_string(&key).map(|()| key))
+                    .map_err(|err| tag(err, variant))?;
+                *cur_key = Some(key);
                 Ok(())
             }
+            OrderedKeyCompound::Skip { .. } => Ok(()),
+            OrderedKeyCompound::Seq { .. } => unreachable!(),
         }
     }
 
-    fn serialize_value<T: ?Sized>(&mut self, value: &T) -> Result<()>
+    fn serialize_value<T>(&mut self, value: &T) -> Result<()>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         match *self {
-            OrderedKeyCompound::Map { ref mut ser, .. } => {
-                ser.writer.write_all(b":")?;
-                value.serialize(&mut **ser)?;
+            OrderedKeyCompound::Map {
+                ref mut ser,
+                ref mut buf,
+                ref mut entries,
+                ref mut cur_key,
+                variant,
+                ref mut cursor,
+            } => {
+                let key = cur_key
+                    .take()
+                    .expect("serialize_value called before serialize_key");
+                let child = match ser.projection {
+                    Some(ref projection) => match projection.step(cursor, &key, false) {
+                        Some(child) => child,
+                        None => return Ok(()),
+                    },
+                    None => Cursor::default(),
+                };
+                let start = buf.len();
+                let mut nested = ser.nested(buf);
+                nested.cursor = child;
+                value
+                    .serialize(&mut nested)
+                    .map_err(|err| tag(err.at(&key), variant))?;
+                ser.writer.count = nested.writer.count;
+                entries.push((key, start..buf.len()));
 
                 Ok(())
             }
+            OrderedKeyCompound::Skip { .. } => Ok(()),
+            OrderedKeyCompound::Seq { .. } => unreachable!(),
         }
     }
 
     fn end(self) -> Result<()> {
-        match self {
-            OrderedKeyCompound::Map { ser, state, .. } => {
-                match state {
-                    State::Empty => {}
-                    _ => ser.writer.write_all(b"}")?,
-                }
-                Ok(())
-            }
-        }
+        self.end_map()?;
+        Ok(())
     }
 }
 
@@ -542,22 +1028,16 @@ where
     type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()>
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
-        match *self {
-            OrderedKeyCompound::Map { .. } => {
-                ser::SerializeMap::serialize_key(self, key)?;
-                ser::SerializeMap::serialize_value(self, value)
-            }
-        }
+        ser::SerializeMap::serialize_key(self, key)?;
+        ser::SerializeMap::serialize_value(self, value)
     }
 
     fn end(self) -> Result<()> {
-        match self {
-            OrderedKeyCompound::Map { .. } => ser::SerializeMap::end(self),
-        }
+        ser::SerializeMap::end(self)
     }
 }
 
@@ -572,34 +1052,17 @@ where
     where
         T: ?Sized + Serialize,
     {
-        match self {
-            OrderedKeyCompound::Map { .. } => {
-                ser::SerializeStruct::serialize_field(self, key, value)
-            }
-        }
+        ser::SerializeStruct::serialize_field(self, key, value)
     }
 
     fn end(self) -> Result<()> {
-        match self {
-            OrderedKeyCompound::Map { ser, state, .. } => {
-                match state {
-                    State::Empty => {}
-                    _ => ser.writer.write_all(b"}")?,
-                }
-
-                ser.writer.write_all(b"}")?;
-                Ok(())
-            }
-        }
+        let ser = self.end_map()?;
+        ser.writer.write_all(b"}")?;
+        ser.leave();
+        ser.check_output()
     }
 }
 
-fn escape_char<W: io::Write>(writer: &mut W, v: char) -> Result<()> {
-    let mut s = String::new();
-    s.push(v);
-    escape_str(writer, &s)
-}
-
 fn escape_str<W: io::Write>(writer: &mut W, v: &str) -> Result<()> {
     writer.write_all(b"\"")?;
 
@@ -661,179 +1124,140 @@ static ESCAPE: [u8; 256] = [
     0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // F
 ];
 
-struct AscendingKeySerializer<'a, W>
-where
-    W: io::Write,
-{
-    ser: &'a mut Serializer<W>,
-    cur_key: Option<String>,
+// Map keys are rendered to a `String` so that the members of an object can be
+// sorted before any of them is written.
+struct MapKeySerializer {
+    // whether scalar keys are written as strings rather than rejected
+    stringify: bool,
 }
 
-impl<'a, W> ser::Serializer for &'a mut AscendingKeySerializer<'a, W>
-where
-    W: io::Write,
-{
-    type Ok = ();
+impl MapKeySerializer {
+    fn stringify<T: fmt::Display>(self, v: T) -> Result<String> {
+        if self.stringify {
+            Ok(v.to_string())
+        } else {
+            Err(Error::canonical(ErrorKind::NonStringKey, v))
+        }
+    }
+}
+
+fn key_must_be_a_string() -> Error {
+    Error::canonical(ErrorKind::NonStringKey, "")
+}
+
+impl ser::Serializer for MapKeySerializer {
+    type Ok = String;
     type Error = Error;
 
-    type SerializeSeq = Impossible<(), Error>;
-    type SerializeTuple = Impossible<(), Error>;
-    type SerializeTupleStruct = Impossible<(), Error>;
-    type SerializeTupleVariant = Impossible<(), Error>;
-    type SerializeMap = Impossible<(), Error>;
-    type SerializeStruct = Impossible<(), Error>;
-    type SerializeStructVariant = Impossible<(), Error>;
+    type SerializeSeq = Impossible<String, Error>;
+    type SerializeTuple = Impossible<String, Error>;
+    type SerializeTupleStruct = Impossible<String, Error>;
+    type SerializeTupleVariant = Impossible<String, Error>;
+    type SerializeMap = Impossible<String, Error>;
+    type SerializeStruct = Impossible<String, Error>;
+    type SerializeStructVariant = Impossible<String, Error>;
 
-    fn serialize_str(self, v: &str) -> Result<()> {
-        match self.cur_key {
-            Some(ref cur_key) if v == cur_key => {
-                Err(Error::Custom(String::from(format!("repeated key: {}", v))))
-            }
-            Some(ref cur_key) if v < cur_key => {
-                Err(Error::Custom(String::from(format!("unordered key: {}", v))))
-            }
-            _ => {
-                self.cur_key = Some(v.to_string());
-                self.ser.serialize_str(v)
-            }
-        }
+    fn serialize_str(self, v: &str) -> Result<String> {
+        Ok(v.to_string())
     }
 
-    fn serialize_bool(self, v: bool) -> Result<()> {
-        Err(Error::Custom(String::from(format!(
-            "key must be a string: {}",
-            v
-        ))))
+    fn serialize_bool(self, v: bool) -> Result<String> {
+        self.stringify(v)
     }
 
-    fn serialize_i8(self, v: i8) -> Result<()> {
-        Err(Error::Custom(String::from(format!(
-            "key must be a string: {}",
-            v
-        ))))
+    fn serialize_i8(self, v: i8) -> Result<String> {
+        self.stringify(v)
     }
 
-    fn serialize_i16(self, v: i16) -> Result<()> {
-        Err(Error::Custom(String::from(format!(
-            "key must be a string: {}",
-            v
-        ))))
+    fn serialize_i16(self, v: i16) -> Result<String> {
+        self.stringify(v)
     }
 
-    fn serialize_i32(self, v: i32) -> Result<()> {
-        Err(Error::Custom(String::from(format!(
-            "key must be a string: {}",
-            v
-        ))))
+    fn serialize_i32(self, v: i32) -> Result<String> {
+        self.stringify(v)
     }
 
-    fn serialize_i64(self, v: i64) -> Result<()> {
-        Err(Error::Custom(String::from(format!(
-            "key must be a string: {}",
-            v
-        ))))
+    fn serialize_i64(self, v: i64) -> Result<String> {
+        self.stringify(v)
     }
 
-    fn serialize_u8(self, v: u8) -> Result<()> {
-        Err(Error::Custom(String::from(format!(
-            "key must be a string: {}",
-            v
-        ))))
+    fn serialize_i128(self, v: i128) -> Result<String> {
+        self.stringify(v)
     }
 
-    fn serialize_u16(self, v: u16) -> Result<()> {
-        Err(Error::Custom(String::from(format!(
-            "key must be a string: {}",
-            v
-        ))))
+    fn serialize_u8(self, v: u8) -> Result<String> {
+        self.stringify(v)
     }
 
-    fn serialize_u32(self, v: u32) -> Result<()> {
-        Err(Error::Custom(String::from(format!(
-            "key must be a string: {}",
-            v
-        ))))
+    fn serialize_u16(self, v: u16) -> Result<String> {
+        self.stringify(v)
     }
 
-    fn serialize_u64(self, v: u64) -> Result<()> {
-        Err(Error::Custom(String::from(format!(
-            "key must be a string: {}",
-            v
-        ))))
+    fn serialize_u32(self, v: u32) -> Result<String> {
+        self.stringify(v)
     }
 
-    fn serialize_f32(self, v: f32) -> Result<()> {
-        Err(Error::Custom(String::from(format!(
-            "key must be a string: {}",
-            v
-        ))))
+    fn serialize_u64(self, v: u64) -> Result<String> {
+        self.stringify(v)
     }
 
-    fn serialize_f64(self, v: f64) -> Result<()> {
-        Err(Error::Custom(String::from(format!(
-            "key must be a string: {}",
-            v
-        ))))
+    fn serialize_u128(self, v: u128) -> Result<String> {
+        self.stringify(v)
     }
 
-    fn serialize_char(self, v: char) -> Result<()> {
-        Err(Error::Custom(String::from(format!(
-            "key must be a string: {}",
-            v
-        ))))
+    fn serialize_f32(self, v: f32) -> Result<String> {
+        Err(Error::canonical(ErrorKind::NonStringKey, v))
     }
 
-    fn serialize_bytes(self, _v: &[u8]) -> Result<()> {
-        Err(Error::Custom(String::from(
-            format!("key must be a string",),
-        )))
+    fn serialize_f64(self, v: f64) -> Result<String> {
+        Err(Error::canonical(ErrorKind::NonStringKey, v))
     }
 
-    fn serialize_none(self) -> Result<()> {
-        Err(Error::Custom(String::from(
-            format!("key must be a string",),
-        )))
+    fn serialize_char(self, v: char) -> Result<String> {
+        self.stringify(v)
     }
 
-    fn serialize_some<T>(self, _value: &T) -> Result<()>
+    fn serialize_bytes(self, _v: &[u8]) -> Result<String> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_none(self) -> Result<String> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_some<T>(self, _value: &T) -> Result<String>
     where
         T: ?Sized + Serialize,
     {
-        Err(Error::Custom(String::from(
-            format!("key must be a string",),
-        )))
+        Err(key_must_be_a_string())
     }
 
-    fn serialize_unit(self) -> Result<()> {
-        Err(Error::Custom(String::from(
-            format!("key must be a string",),
-        )))
+    fn serialize_unit(self) -> Result<String> {
+        Err(key_must_be_a_string())
     }
 
-    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
-        Err(Error::Custom(String::from(
-            format!("key must be a string",),
-        )))
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<String> {
+        Err(key_must_be_a_string())
     }
 
     fn serialize_unit_variant(
         self,
         _name: &'static str,
         _variant_index: u32,
-        _variant: &'static str,
-    ) -> Result<()> {
-        Err(Error::Custom(String::from(
-            format!("key must be a string",),
-        )))
+        variant: &'static str,
+    ) -> Result<String> {
+        self.stringify(variant)
     }
 
-    fn serialize_newtype_struct<T>(self, _name: &'static str, _value: &T) -> Result<()>
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<String>
     where
         T: ?Sized + Serialize,
     {
-        Err(Error::Custom(String::from(
-            format!("key must be a string",),
-        )))
+        if self.stringify {
+            value.serialize(self)
+        } else {
+            Err(key_must_be_a_string())
+        }
     }
 
     fn serialize_newtype_variant<T>(
@@ -842,25 +1266,19 @@ where
         _variant_index: u32,
         _variant: &'static str,
         _value: &T,
-    ) -> Result<()>
+    ) -> Result<String>
     where
         T: ?Sized + Serialize,
     {
-        Err(Error::Custom(String::from(
-            format!("key must be a string",),
-        )))
+        Err(key_must_be_a_string())
     }
 
     fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
-        Err(Error::Custom(String::from(
-            format!("key must be a string",),
-        )))
+        Err(key_must_be_a_string())
     }
 
     fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
-        Err(Error::Custom(String::from(
-            format!("key must be a string",),
-        )))
+        Err(key_must_be_a_string())
     }
 
     fn serialize_tuple_struct(
@@ -868,9 +1286,7 @@ where
         _name: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeTupleStruct> {
-        Err(Error::Custom(String::from(
-            format!("key must be a string",),
-        )))
+        Err(key_must_be_a_string())
     }
 
     fn serialize_tuple_variant(
@@ -880,25 +1296,17 @@ where
         _variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeTupleVariant> {
-        Err(Error::Custom(String::from(
-            format!("key must be a string",),
-        )))
+        Err(key_must_be_a_string())
     }
 
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
-        Err(Error::Custom(String::from(
-            format!("key must be a string",),
-        )))
+        Err(key_must_be_a_string())
     }
 
     fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
-        Err(Error::Custom(String::from(
-            format!("key must be a string",),
-        )))
+        Err(key_must_be_a_string())
     }
 
-    // Struct variants are represented in JSON as `{ NAME: { K: V, ... } }`.
-    // This is the externally tagged representation.
     fn serialize_struct_variant(
         self,
         _name: &'static str,
@@ -906,8 +1314,6 @@ where
         _variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeStructVariant> {
-        Err(Error::Custom(String::from(
-            format!("key must be a string",),
-        )))
+        Err(key_must_be_a_string())
     }
 }
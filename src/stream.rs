@@ -0,0 +1,244 @@
+//! Re-encodes arbitrary JSON as canonical JSON without building a document
+//! tree.
+//!
+//! The input is parsed with `serde_json` and every event is fed straight into
+//! the canonical `Serializer`. Arrays and scalars are written as soon as they
+//! are read; only the members of each object are held back until the object
+//! ends, so memory is bounded by the largest single object rather than by the
+//! whole document.
+
+use super::error::Result;
+use super::limits::Limits;
+use super::ser::Serializer;
+use serde::{de, ser, ser::SerializeMap, ser::SerializeSeq, Serialize};
+use std::{cell::RefCell, fmt, io};
+
+/// Reads one JSON document from `reader` and writes its canonical form to
+/// `writer`, and flushes it.
+///
+/// Output is produced while the input is still being read, so on error
+/// `writer` may already have received part of the document. Readers and
+/// writers are used unbuffered; wrap them in `BufReader`/`BufWriter` where
+/// that matters. For newline-delimited JSON, call this once per line.
+pub fn canonicalize<R, W>(reader: R, writer: W) -> Result<()>
+where
+    R: io::Read,
+    W: io::Write,
+{
+    canonicalize_with_limits(reader, writer, Limits::default())
+}
+
+/// `canonicalize` for untrusted input: stops with an error as soon as the
+/// document goes over one of `limits`. `serde_json` refuses to nest deeper
+/// than 128 levels on its own, whatever the limits say.
+pub fn canonicalize_with_limits<R, W>(reader: R, writer: W, limits: Limits) -> Result<()>
+where
+    R: io::Read,
+    W: io::Write,
+{
+    let mut de = serde_json::Deserializer::from_reader(reader);
+    let mut ser = Serializer::new(writer).parsed_floats(true).limits(limits);
+    Transcoder::new(&mut de).serialize(&mut ser)?;
+    de.end()?;
+    // a `BufWriter` passed by value would otherwise lose the error of its
+    // last flush when dropped
+    ser.into_inner().flush()?;
+    Ok(())
+}
+
+// A deserializer that serializes whatever it yields. Errors raised by the
+// serializer have to travel through the deserializer, whose error type cannot
+// carry them; they are parked in a slot next to the visitor and picked up
+// again on the way out so that their kind is not lost.
+struct Transcoder<D>(RefCell<Option<D>>);
+
+impl<D> Transcoder<D> {
+    fn new(de: D) -> Self {
+        Transcoder(RefCell::new(Some(de)))
+    }
+}
+
+impl<'de, D> Serialize for Transcoder<D>
+where
+    D: de::Deserializer<'de>,
+{
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        let de = self
+            .0
+            .borrow_mut()
+            .take()
+            .expect("transcoder serialized twice");
+        let mut error = None;
+        de.deserialize_any(Visitor {
+            ser: serializer,
+            error: &mut error,
+        })
+        .map_err(|err| error.take().unwrap_or_else(|| ser::Error::custom(err)))
+    }
+}
+
+fn park<S, D>(slot: &mut Option<S>, err: S) -> D
+where
+    S: ser::Error,
+    D: de::Error,
+{
+    let forwarded = D::custom(&err);
+    *slot = Some(err);
+    forwarded
+}
+
+struct Visitor<'a, S>
+where
+    S: ser::Serializer,
+{
+    ser: S,
+    error: &'a mut Option<S::Error>,
+}
+
+impl<'a, S> Visitor<'a, S>
+where
+    S: ser::Serializer,
+{
+    fn write<E, F>(self, f: F) -> std::result::Result<S::Ok, E>
+    where
+        E: de::Error,
+        F: FnOnce(S) -> std::result::Result<S::Ok, S::Error>,
+    {
+        let Visitor { ser, error } = self;
+        f(ser).map_err(|e| park(error, e))
+    }
+}
+
+impl<'de, 'a, S> de::Visitor<'de> for Visitor<'a, S>
+where
+    S: ser::Serializer,
+{
+    type Value = S::Ok;
+
+    fn expecting(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("any JSON value")
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> std::result::Result<S::Ok, E> {
+        self.write(|ser| ser.serialize_bool(v))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> std::result::Result<S::Ok, E> {
+        self.write(|ser| ser.serialize_i64(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> std::result::Result<S::Ok, E> {
+        self.write(|ser| ser.serialize_u64(v))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> std::result::Result<S::Ok, E> {
+        self.write(|ser| ser.serialize_f64(v))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> std::result::Result<S::Ok, E> {
+        self.write(|ser| ser.serialize_str(v))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> std::result::Result<S::Ok, E> {
+        self.write(|ser| ser.serialize_unit())
+    }
+
+    fn visit_seq<A>(self, mut access: A) -> std::result::Result<S::Ok, A::Error>
+    where
+        A: de::SeqAccess<'de>,
+    {
+        let Visitor { ser, error } = self;
+        let mut seq = ser
+            .serialize_seq(access.size_hint())
+            .map_err(|e| park(error, e))?;
+        while access
+            .next_element_seed(ElementSeed {
+                seq: &mut seq,
+                error: &mut *error,
+            })?
+            .is_some()
+        {}
+        seq.end().map_err(|e| park(error, e))
+    }
+
+    fn visit_map<A>(self, mut access: A) -> std::result::Result<S::Ok, A::Error>
+    where
+        A: de::MapAccess<'de>,
+    {
+        let Visitor { ser, error } = self;
+        let mut map = ser
+            .serialize_map(access.size_hint())
+            .map_err(|e| park(error, e))?;
+        while access
+            .next_key_seed(MemberSeed {
+                map: &mut map,
+                error: &mut *error,
+                key: true,
+            })?
+            .is_some()
+        {
+            access.next_value_seed(MemberSeed {
+                map: &mut map,
+                error: &mut *error,
+                key: false,
+            })?;
+        }
+        map.end().map_err(|e| park(error, e))
+    }
+}
+
+struct ElementSeed<'a, T>
+where
+    T: SerializeSeq,
+{
+    seq: &'a mut T,
+    error: &'a mut Option<T::Error>,
+}
+
+impl<'de, 'a, T> de::DeserializeSeed<'de> for ElementSeed<'a, T>
+where
+    T: SerializeSeq,
+{
+    type Value = ();
+
+    fn deserialize<D>(self, de: D) -> std::result::Result<(), D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        self.seq
+            .serialize_element(&Transcoder::new(de))
+            .map_err(|e| park(self.error, e))
+    }
+}
+
+struct MemberSeed<'a, T>
+where
+    T: SerializeMap,
+{
+    map: &'a mut T,
+    error: &'a mut Option<T::Error>,
+    key: bool,
+}
+
+impl<'de, 'a, T> de::DeserializeSeed<'de> for MemberSeed<'a, T>
+where
+    T: SerializeMap,
+{
+    type Value = ();
+
+    fn deserialize<D>(self, de: D) -> std::result::Result<(), D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let transcoder = Transcoder::new(de);
+        let result = if self.key {
+            self.map.serialize_key(&transcoder)
+        } else {
+            self.map.serialize_value(&transcoder)
+        };
+        result.map_err(|e| park(self.error, e))
+    }
+}
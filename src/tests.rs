@@ -1,4 +1,17 @@
-use crate::{error::Error, ser::to_string};
+// The oldest tests here still use the module constants such as
+// `std::f64::NAN` and pass some values by reference where they could be moved.
+#![allow(
+    clippy::legacy_numeric_constants,
+    clippy::needless_borrows_for_generic_args
+)]
+
+use crate::{
+    de,
+    error::{Error, ErrorKind},
+    jcs,
+    ser::to_string,
+    stream, validate,
+};
 use serde_derive::*;
 use serde_json::Value;
 use std::{i16, i32, i64, i8, u16, u32, u64, u8};
@@ -26,7 +39,7 @@ where
     T: serde::ser::Serialize,
 {
     match to_string(&val).unwrap_err() {
-        Error::Custom(_) => (),
+        Error::Canonical { .. } => (),
         _ => panic!("this should error out"),
     }
 }
@@ -181,6 +194,9 @@ fn write_f64() {
         (46.0f64, "46"),
         (-254.0f64, "-254"),
         (1234567f64, "1234567"),
+        (9223372036854775808f64, "9223372036854775808"),
+        (18446744073709551616f64, "18446744073709551616"),
+        (-9223372036854775808f64, "-9223372036854775808"),
     ];
     assert_encode_ok(tests);
 }
@@ -473,3 +489,2588 @@ fn write_enum_derive() {
     let expected = r#"{"Struct":{"a":1}}"#;
     assert_encode(&s, expected);
 }
+
+#[test]
+fn jcs_write_numbers() {
+    // IEEE 754 test vectors from RFC 8785, appendix B
+    let tests: &[(u64, &str)] = &[
+        (0x0000000000000000, "0"),
+        (0x8000000000000000, "0"),
+        (0x0000000000000001, "5e-324"),
+        (0x8000000000000001, "-5e-324"),
+        (0x7fefffffffffffff, "1.7976931348623157e+308"),
+        (0xffefffffffffffff, "-1.7976931348623157e+308"),
+        (0x4340000000000000, "9007199254740992"),
+        (0xc340000000000000, "-9007199254740992"),
+        (0x4430000000000000, "295147905179352830000"),
+        (0x44b52d02c7e14af5, "9.999999999999997e+22"),
+        (0x44b52d02c7e14af6, "1e+23"),
+        (0x44b52d02c7e14af7, "1.0000000000000001e+23"),
+        (0x444b1ae4d6e2ef4e, "999999999999999700000"),
+        (0x444b1ae4d6e2ef4f, "999999999999999900000"),
+        (0x444b1ae4d6e2ef50, "1e+21"),
+        (0x3eb0c6f7a0b5ed8c, "9.999999999999997e-7"),
+        (0x3eb0c6f7a0b5ed8d, "0.000001"),
+        (0x41b3de4355555553, "333333333.3333332"),
+        (0x41b3de4355555554, "333333333.33333325"),
+        (0x41b3de4355555555, "333333333.3333333"),
+        (0x41b3de4355555556, "333333333.3333334"),
+        (0x41b3de4355555557, "333333333.33333343"),
+        (0xbecbf647612f3696, "-0.0000033333333333333333"),
+        (0x43143ff3c1cb0959, "1424953923781206.2"),
+    ];
+    for &(bits, expected) in tests {
+        assert_eq!(jcs::to_string(&f64::from_bits(bits)).unwrap(), expected);
+    }
+
+    assert_eq!(jcs::to_string(&0.1f32).unwrap(), "0.1");
+    assert_eq!(jcs::to_string(&-17i8).unwrap(), "-17");
+    assert_eq!(
+        jcs::to_string(&(1u64 << 60)).unwrap(),
+        "1152921504606847000"
+    );
+    assert!(jcs::to_string(&u64::MAX).is_err());
+    assert!(jcs::to_string(&f64::NAN).is_err());
+    assert!(jcs::to_string(&f64::INFINITY).is_err());
+}
+
+#[test]
+fn jcs_write_string() {
+    let tests = &[
+        ("", "\"\""),
+        ("\\", "\"\\\\\""),
+        ("\"", "\"\\\"\""),
+        ("\u{8}\t\n\u{c}\r", "\"\\b\\t\\n\\f\\r\""),
+        ("\u{0}\u{f}\u{1f}", "\"\\u0000\\u000f\\u001f\""),
+        ("\u{7f}/\u{20ac}", "\"\u{7f}/\u{20ac}\""),
+    ];
+    for &(value, expected) in tests {
+        assert_eq!(jcs::to_string(value).unwrap(), expected);
+    }
+}
+
+#[test]
+fn jcs_sort_utf16() {
+    // RFC 8785, section 3.2.3
+    let mut map = HashMap::new();
+    map.insert("\u{20ac}", "Euro Sign");
+    map.insert("\r", "Carriage Return");
+    map.insert("\u{fb33}", "Hebrew Letter Dalet With Dagesh");
+    map.insert("1", "One");
+    map.insert("\u{1f600}", "Emoji: Grinning Face");
+    map.insert("\u{80}", "Control");
+    map.insert("\u{f6}", "Latin Small Letter O With Diaeresis");
+
+    let expected = "{\"\\r\":\"Carriage Return\",\"1\":\"One\",\"\u{80}\":\"Control\",\
+                    \"\u{f6}\":\"Latin Small Letter O With Diaeresis\",\
+                    \"\u{20ac}\":\"Euro Sign\",\"\u{1f600}\":\"Emoji: Grinning Face\",\
+                    \"\u{fb33}\":\"Hebrew Letter Dalet With Dagesh\"}";
+    assert_eq!(jcs::to_string(&map).unwrap(), expected);
+
+    // code point order puts the emoji last
+    let olpc = to_string(&map).unwrap();
+    assert!(olpc.ends_with("\"\u{1f600}\":\"Emoji: Grinning Face\"}"));
+}
+
+#[test]
+fn jcs_rfc_example() {
+    // RFC 8785, section 3.2.2
+    let value: Value = serde_json::from_str(
+        r#"{
+            "numbers": [333333333.33333329, 1E30, 4.50, 2e-3, 0.000000000000000000000000001],
+            "string": "\u20ac$\u000F\u000aA'\u0042\u0022\u005c\\\"\/",
+            "literals": [null, true, false]
+        }"#,
+    )
+    .unwrap();
+    let expected = r#"{"literals":[null,true,false],"numbers":[333333333.3333333,1e+30,4.5,0.002,1e-27],"string":"€$\u000f\nA'B\"\\\\\"/"}"#;
+    assert_eq!(jcs::to_string(&value).unwrap(), expected);
+}
+
+#[test]
+fn jcs_struct_and_variants() {
+    #[derive(Serialize)]
+    enum E {
+        Struct { b: f64, a: &'static str },
+    }
+
+    let s = E::Struct { b: 1.5, a: "x" };
+    assert_eq!(
+        jcs::to_string(&s).unwrap(),
+        r#"{"Struct":{"a":"x","b":1.5}}"#
+    );
+}
+
+fn assert_decode_err(input: &str, kind: ErrorKind, offset: usize) {
+    match de::from_str::<Value>(input).unwrap_err() {
+        Error::Parse {
+            kind: actual_kind,
+            offset: actual_offset,
+        } => assert_eq!(
+            (actual_kind, actual_offset),
+            (kind, offset),
+            "input: {}",
+            input
+        ),
+        err => panic!("unexpected error for {}: {}", input, err),
+    }
+}
+
+#[test]
+fn read_canonical() {
+    let tests = &[
+        "null",
+        "true",
+        "-12",
+        "18446744073709551615",
+        "\"a\\\"b\\\\c\n\u{2603}\"",
+        "[]",
+        "[1,[2,{}],\"x\"]",
+        r#"{"":0,"a":{"b":[true,false,null]},"b":"c"}"#,
+    ];
+    for &input in tests {
+        let value: Value = de::from_str(input).unwrap();
+        assert_eq!(to_string(&value).unwrap(), input);
+    }
+}
+
+#[test]
+fn read_borrowed_and_derived() {
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Test<'a> {
+        a: Option<u32>,
+        b: &'a str,
+        c: Animal,
+    }
+
+    let input = r#"{"a":null,"b":"bee","c":{"Cat":{"age":5,"name":"Kate"}}}"#;
+    let test: Test<'_> = de::from_str(input).unwrap();
+    assert_eq!(
+        test,
+        Test {
+            a: None,
+            b: "bee",
+            c: Animal::Cat {
+                age: 5,
+                name: "Kate".to_string()
+            },
+        }
+    );
+
+    let animals: Vec<Animal> = de::from_slice(br#"["Dog",{"Frog":["Henry",[349]]}]"#).unwrap();
+    assert_eq!(
+        animals,
+        vec![Animal::Dog, Animal::Frog("Henry".to_string(), vec![349])]
+    );
+
+    let from_reader: BTreeMap<String, u8> = de::from_reader(&b"{\"x\":1}"[..]).unwrap();
+    assert_eq!(from_reader, treemap!("x".to_string() => 1));
+}
+
+#[test]
+fn read_non_canonical() {
+    assert_decode_err(" 1", ErrorKind::Whitespace, 0);
+    assert_decode_err("1\n", ErrorKind::Whitespace, 1);
+    assert_decode_err("[1, 2]", ErrorKind::Whitespace, 3);
+    assert_decode_err(r#"{"a": 1}"#, ErrorKind::Whitespace, 5);
+    assert_decode_err(r#"{"b":1,"a":2}"#, ErrorKind::UnorderedKey, 7);
+    assert_decode_err(r#"{"a":1,"a":2}"#, ErrorKind::DuplicateKey, 7);
+    assert_decode_err("[1.5]", ErrorKind::Float, 1);
+    assert_decode_err("1e3", ErrorKind::Float, 0);
+    assert_decode_err("[01]", ErrorKind::LeadingZero, 1);
+    assert_decode_err("-0", ErrorKind::NegativeZero, 0);
+    assert_decode_err(
+        "340282366920938463463374607431768211456",
+        ErrorKind::NumberOutOfRange,
+        0,
+    );
+    assert_decode_err(r#""a\nb""#, ErrorKind::NonMinimalEscape, 2);
+    assert_decode_err(r#""\u0041""#, ErrorKind::NonMinimalEscape, 1);
+    assert_decode_err(r#""\/""#, ErrorKind::NonMinimalEscape, 1);
+    assert_decode_err(r#""\q""#, ErrorKind::InvalidEscape, 1);
+    assert_decode_err("[1,]", ErrorKind::UnexpectedByte, 3);
+    assert_decode_err("[1", ErrorKind::UnexpectedEof, 2);
+    assert_decode_err("nul", ErrorKind::UnexpectedEof, 3);
+    assert_decode_err("{} {}", ErrorKind::Whitespace, 2);
+    assert_decode_err("{}{}", ErrorKind::TrailingCharacters, 2);
+
+    match de::from_slice::<Value>(b"\"a\xffb\"").unwrap_err() {
+        Error::Parse { kind, offset } => assert_eq!((kind, offset), (ErrorKind::InvalidUtf8, 2)),
+        err => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn validate_canonical() {
+    for entry in std::fs::read_dir("testdata").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|ext| ext != "json") {
+            continue;
+        }
+        let value: Value = serde_json::from_reader(std::fs::File::open(&path).unwrap()).unwrap();
+        let canonical = crate::ser::to_vec(&value).unwrap();
+        assert_eq!(validate::validate(&canonical), vec![], "{:?}", path);
+        assert!(validate::is_canonical(&canonical), "{:?}", path);
+    }
+}
+
+#[test]
+fn validate_reports_every_violation() {
+    let input = "{\"b\": [1.5, 01],\n \"a\": {\"x/y\": \"\\n\", \"x/y\": -0}}";
+    let violations: Vec<_> = validate::validate(input.as_bytes())
+        .into_iter()
+        .map(|v| (v.kind, v.offset, v.line, v.column, v.pointer))
+        .collect();
+
+    assert_eq!(
+        violations,
+        vec![
+            (ErrorKind::Whitespace, 5, 1, 6, "/b".to_string()),
+            (ErrorKind::Float, 7, 1, 8, "/b/0".to_string()),
+            (ErrorKind::Whitespace, 11, 1, 12, "/b/1".to_string()),
+            (ErrorKind::LeadingZero, 12, 1, 13, "/b/1".to_string()),
+            (ErrorKind::Whitespace, 16, 1, 17, "".to_string()),
+            (ErrorKind::UnorderedKey, 18, 2, 2, "/a".to_string()),
+            (ErrorKind::Whitespace, 22, 2, 6, "/a".to_string()),
+            (ErrorKind::Whitespace, 30, 2, 14, "/a/x~1y".to_string()),
+            (
+                ErrorKind::NonMinimalEscape,
+                32,
+                2,
+                16,
+                "/a/x~1y".to_string()
+            ),
+            (ErrorKind::Whitespace, 36, 2, 20, "/a".to_string()),
+            (ErrorKind::DuplicateKey, 37, 2, 21, "/a/x~1y".to_string()),
+            (ErrorKind::Whitespace, 43, 2, 27, "/a/x~1y".to_string()),
+            (ErrorKind::NegativeZero, 44, 2, 28, "/a/x~1y".to_string()),
+        ]
+    );
+    assert!(!validate::is_canonical(input.as_bytes()));
+}
+
+#[test]
+fn validate_stops_at_malformed_json() {
+    let violations = validate::validate(b"[1 ,");
+    let kinds: Vec<_> = violations.iter().map(|v| (v.kind, v.offset)).collect();
+    assert_eq!(
+        kinds,
+        vec![(ErrorKind::Whitespace, 2), (ErrorKind::UnexpectedEof, 4)]
+    );
+
+    let violations = validate::validate(b"{\"a\":1}x");
+    assert_eq!(violations[0].kind, ErrorKind::TrailingCharacters);
+}
+
+#[test]
+fn validate_limits() {
+    use crate::limits::{Limits, DEFAULT_MAX_DEPTH};
+
+    let hostile = "[".repeat(200_000);
+    let violations = validate::validate(hostile.as_bytes());
+    assert_eq!(violations.len(), 1);
+    assert_eq!(violations[0].kind, ErrorKind::TooDeep);
+    assert_eq!(violations[0].offset, DEFAULT_MAX_DEPTH);
+    assert!(!validate::is_canonical(hostile.as_bytes()));
+
+    let limits = Limits::default().max_depth(2).max_string_len(2).max_keys(1);
+    let violations = validate::validate_with_limits(br#"[{"a":"abc","b":[[]]}]"#, limits);
+    let kinds: Vec<_> = violations
+        .iter()
+        .map(|v| (v.kind, v.offset, v.pointer.as_str()))
+        .collect();
+    assert_eq!(
+        kinds,
+        vec![
+            (ErrorKind::StringTooLong, 6, "/0/a"),
+            (ErrorKind::TooManyKeys, 12, "/0"),
+            (ErrorKind::TooDeep, 16, "/0/b"),
+        ]
+    );
+    assert!(validate::is_canonical_with_limits(b"[[]]", limits));
+    assert!(!validate::is_canonical_with_limits(b"[[[]]]", limits));
+}
+
+#[test]
+fn validate_and_serializer_agree() {
+    let kind = |err| match err {
+        Error::Canonical { kind, .. } => kind,
+        err => panic!("unexpected error: {}", err),
+    };
+
+    assert_eq!(kind(to_string(&1.5f64).unwrap_err()), ErrorKind::Float);
+    assert_eq!(validate::validate(b"1.5")[0].kind, ErrorKind::Float);
+
+    #[derive(Serialize)]
+    struct Duplicate {
+        a: u8,
+        #[serde(rename = "a")]
+        b: u8,
+    }
+    assert_eq!(
+        kind(to_string(&Duplicate { a: 1, b: 2 }).unwrap_err()),
+        ErrorKind::DuplicateKey
+    );
+    assert_eq!(
+        validate::validate(br#"{"a":1,"a":2}"#)[0].kind,
+        ErrorKind::DuplicateKey
+    );
+}
+
+#[test]
+fn write_single_pass() {
+    use std::cell::Cell;
+
+    struct Counted<'a>(&'a Cell<usize>);
+
+    impl<'a> serde::Serialize for Counted<'a> {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            self.0.set(self.0.get() + 1);
+            serializer.serialize_u8(1)
+        }
+    }
+
+    let calls = Cell::new(0);
+    let value = treemap!("b" => Counted(&calls), "a" => Counted(&calls));
+    assert_encode(&value, r#"{"a":1,"b":1}"#);
+    assert_eq!(calls.get(), 2);
+}
+
+#[test]
+fn write_nothing_on_error() {
+    let mut out = Vec::new();
+    let value = (vec![1, 2, 3], "a", 1.5f64);
+    assert!(crate::ser::to_writer(&mut out, &value).is_err());
+    assert!(out.is_empty());
+
+    let mut out = Vec::new();
+    assert!(jcs::to_writer(&mut out, &(1, f64::NAN)).is_err());
+    assert!(out.is_empty());
+}
+
+#[test]
+fn canonicalize_matches_serializer() {
+    for entry in std::fs::read_dir("testdata").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|ext| ext != "json") {
+            continue;
+        }
+        let value: Value = serde_json::from_reader(std::fs::File::open(&path).unwrap()).unwrap();
+        let mut out = Vec::new();
+        stream::canonicalize(std::fs::File::open(&path).unwrap(), &mut out).unwrap();
+        assert_eq!(out, crate::ser::to_vec(&value).unwrap(), "{:?}", path);
+    }
+}
+
+#[test]
+fn canonicalize_lines() {
+    let input = "{\"b\": 2, \"a\": [1, 2.0]}\n[ {\"z\":null,\"y\":\"\\u2603\"} ]\n";
+    let mut out = Vec::new();
+    for line in input.lines() {
+        stream::canonicalize(line.as_bytes(), &mut out).unwrap();
+        out.push(b'\n');
+    }
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "{\"a\":[1,2],\"b\":2}\n[{\"y\":\"\u{2603}\",\"z\":null}]\n"
+    );
+}
+
+#[test]
+fn canonicalize_errors() {
+    let mut out = Vec::new();
+    match stream::canonicalize(&b"{\"a\":[1,{\"b\":1.5}]}"[..], &mut out).unwrap_err() {
+        Error::Canonical { kind, .. } => assert_eq!(kind, ErrorKind::Float),
+        err => panic!("unexpected error: {}", err),
+    }
+
+    match stream::canonicalize(&b"{\"a\":1,\"a\":2}"[..], &mut out).unwrap_err() {
+        Error::Canonical { kind, .. } => assert_eq!(kind, ErrorKind::DuplicateKey),
+        err => panic!("unexpected error: {}", err),
+    }
+
+    assert!(stream::canonicalize(&b"[1,"[..], &mut out).is_err());
+    assert!(stream::canonicalize(&b"[1] [2]"[..], &mut out).is_err());
+}
+
+#[test]
+fn canonicalize_keeps_integers_exact() {
+    let canonicalize = |input: &str| {
+        let mut out = Vec::new();
+        stream::canonicalize(input.as_bytes(), &mut out).map(|()| String::from_utf8(out).unwrap())
+    };
+    assert_eq!(
+        canonicalize("[18446744073709551615,-9223372036854775808,1e3]").unwrap(),
+        "[18446744073709551615,-9223372036854775808,1000]"
+    );
+
+    // `serde_json` reads these as rounded doubles
+    for input in &[
+        "[123456789012345678901234567890]",
+        "{\"a\":18446744073709551616}",
+        "-9223372036854775809",
+        "1e30",
+    ] {
+        let err = canonicalize(input).unwrap_err();
+        assert_eq!(err.kind(), Some(ErrorKind::NumberOutOfRange), "{}", input);
+    }
+    assert_eq!(
+        canonicalize("{\"a\":[1e20]}").unwrap_err().pointer(),
+        Some("/a/0")
+    );
+
+    // values serialized from Rust are not affected
+    assert_eq!(to_string(&1e20).unwrap(), "100000000000000000000");
+}
+
+#[test]
+fn write_128_bit_integers() {
+    assert_eq!(
+        to_string(&u128::MAX).unwrap(),
+        "340282366920938463463374607431768211455"
+    );
+    assert_eq!(
+        to_string(&i128::MIN).unwrap(),
+        "-170141183460469231731687303715884105728"
+    );
+
+    // as keys they need `stringify_keys`, like narrower integers
+    fn stringify<T: serde::Serialize>(value: &T) -> Result<String, Error> {
+        let mut out = Vec::new();
+        value.serialize(&mut crate::ser::Serializer::new(&mut out).stringify_keys(true))?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+    assert_eq!(
+        stringify(&treemap!(u128::MAX => 1, 10u128 => 2)).unwrap(),
+        r#"{"10":2,"340282366920938463463374607431768211455":1}"#
+    );
+    assert_eq!(stringify(&treemap!(-1i128 => 1)).unwrap(), r#"{"-1":1}"#);
+    for err in &[
+        to_string(&treemap!(-1i128 => 1)).unwrap_err(),
+        to_string(&treemap!(1u128 => 1)).unwrap_err(),
+    ] {
+        assert_eq!(err.kind(), Some(ErrorKind::NonStringKey));
+    }
+
+    // JCS writes integers as doubles and refuses to round them
+    assert_eq!(
+        jcs::to_string(&(1u128 << 100)).unwrap(),
+        "1.2676506002282294e+30"
+    );
+    assert_eq!(
+        jcs::to_string(&(u128::MAX - (u128::MAX >> 53))).unwrap(),
+        "3.4028236692093843e+38"
+    );
+    assert_eq!(
+        jcs::to_string(&i128::MIN).unwrap(),
+        "-1.7014118346046923e+38"
+    );
+    for &v in &[i128::MAX, i128::MIN + 1, (1 << 53) + 1] {
+        assert!(jcs::to_string(&v).is_err());
+    }
+    assert!(jcs::to_string(&u128::MAX).is_err());
+}
+
+#[test]
+fn read_128_bit_integers() {
+    let input =
+        "[-170141183460469231731687303715884105728,340282366920938463463374607431768211455]";
+    let value: (i128, u128) = de::from_str(input).unwrap();
+    assert_eq!(value, (i128::MIN, u128::MAX));
+    assert_eq!(to_string(&value).unwrap(), input);
+
+    assert_eq!(
+        de::from_str::<u64>("18446744073709551615").unwrap(),
+        u64::MAX
+    );
+    assert!(de::from_str::<u64>("18446744073709551616").is_err());
+    assert!(validate::is_canonical(input.as_bytes()));
+}
+
+#[test]
+fn write_safe_integers() {
+    fn safe<T: serde::Serialize>(value: &T) -> Result<String, Error> {
+        let mut out = Vec::new();
+        value.serialize(&mut crate::ser::Serializer::new(&mut out).safe_integers(true))?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    let max = (1i64 << 53) - 1;
+    assert_eq!(
+        safe(&vec![max, -max]).unwrap(),
+        "[9007199254740991,-9007199254740991]"
+    );
+    assert_eq!(
+        safe(&treemap!("a" => vec![max as f64])).unwrap(),
+        r#"{"a":[9007199254740991]}"#
+    );
+    // keys are strings and are not affected
+    let mut out = Vec::new();
+    serde::Serialize::serialize(
+        &treemap!(u128::MAX => 1),
+        &mut crate::ser::Serializer::new(&mut out)
+            .safe_integers(true)
+            .stringify_keys(true),
+    )
+    .unwrap();
+    assert_eq!(out, br#"{"340282366920938463463374607431768211455":1}"#);
+
+    for err in &[
+        safe(&(max + 1)).unwrap_err(),
+        safe(&vec![-max - 1]).unwrap_err(),
+        safe(&u64::MAX).unwrap_err(),
+        safe(&treemap!("a" => u128::MAX)).unwrap_err(),
+        safe(&9007199254740992f64).unwrap_err(),
+    ] {
+        match *err {
+            Error::Canonical { kind, .. } => assert_eq!(kind, ErrorKind::NumberOutOfRange),
+            ref err => panic!("unexpected error: {}", err),
+        }
+    }
+
+    // without the guard the same values are written as they are
+    assert_eq!(to_string(&u64::MAX).unwrap(), "18446744073709551615");
+}
+
+#[test]
+fn write_stringified_keys() {
+    #[derive(Serialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    struct Id(u32);
+
+    #[derive(Serialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    enum Color {
+        Red,
+        Green,
+    }
+
+    fn stringify<T: serde::Serialize>(value: &T) -> Result<String, Error> {
+        let mut out = Vec::new();
+        value.serialize(&mut crate::ser::Serializer::new(&mut out).stringify_keys(true))?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    // sorted as strings, not as numbers
+    let numbers: HashMap<u32, u32> = (8..12).map(|i| (i, i)).collect();
+    assert_eq!(
+        stringify(&numbers).unwrap(),
+        r#"{"10":10,"11":11,"8":8,"9":9}"#
+    );
+    assert_eq!(
+        stringify(&treemap!(-1i8 => 0, 2 => 0)).unwrap(),
+        r#"{"-1":0,"2":0}"#
+    );
+    assert_eq!(
+        stringify(&treemap!(true => 1, false => 0)).unwrap(),
+        r#"{"false":0,"true":1}"#
+    );
+    assert_eq!(
+        stringify(&treemap!('b' => 1, '"' => 0)).unwrap(),
+        r#"{"\"":0,"b":1}"#
+    );
+    assert_eq!(
+        stringify(&treemap!(Color::Red => 1, Color::Green => 2)).unwrap(),
+        r#"{"Green":2,"Red":1}"#
+    );
+    assert_eq!(
+        stringify(&treemap!(Id(10) => vec![treemap!(Id(9) => ())])).unwrap(),
+        r#"{"10":[{"9":null}]}"#
+    );
+
+    // keys that only differ before stringification
+    #[derive(Serialize, PartialEq, Eq, PartialOrd, Ord)]
+    #[serde(untagged)]
+    enum Key {
+        Int(u8),
+        Str(&'static str),
+    }
+    match stringify(&treemap!(Key::Int(1) => 0, Key::Str("1") => 1)).unwrap_err() {
+        Error::Canonical { kind, value, .. } => {
+            assert_eq!((kind, value.as_str()), (ErrorKind::DuplicateKey, "1"))
+        }
+        err => panic!("unexpected error: {}", err),
+    }
+
+    // floats and compound keys are still rejected
+    struct FloatKey;
+    impl serde::Serialize for FloatKey {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_map(vec![(1.5f64, 0)])
+        }
+    }
+    for err in [
+        stringify(&FloatKey).unwrap_err(),
+        stringify(&treemap!(vec![1] => 0)).unwrap_err(),
+        stringify(&treemap!(Some(1) => 0)).unwrap_err(),
+    ] {
+        match err {
+            Error::Canonical { kind, .. } => assert_eq!(kind, ErrorKind::NonStringKey),
+            err => panic!("unexpected error: {}", err),
+        }
+    }
+
+    // without the option non-string keys are an error
+    assert_encode_err(&treemap!(1u32 => 0));
+    assert_encode_err(&treemap!(Id(1) => 0));
+    assert_encode_err(&treemap!(Color::Red => 0));
+}
+
+#[test]
+fn write_float_policy() {
+    use crate::ser::{FloatPolicy, Scheme, Serializer};
+
+    fn write<T: serde::Serialize>(policy: FloatPolicy, value: &T) -> Result<String, Error> {
+        let mut out = Vec::new();
+        value.serialize(&mut Serializer::new(&mut out).float_policy(policy))?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    let values = (1.5f64, -0.25f64, 3.0f64, 0.1f32, 1e21f64);
+
+    let shortest = FloatPolicy::Shortest;
+    assert_eq!(write(shortest, &values).unwrap(), "[1.5,-0.25,3,0.1,1e+21]");
+    assert_eq!(write(shortest, &-0.0f64).unwrap(), "0");
+
+    let cents = FloatPolicy::DecimalString { precision: 2 };
+    assert_eq!(
+        write(cents, &values).unwrap(),
+        r#"["1.50","-0.25","3.00","0.10","1000000000000000000000.00"]"#
+    );
+    // rounded half to even on the exact binary value, never signed zero
+    assert_eq!(
+        write(cents, &(0.125f64, 0.375f64, 1.005f64, -0.001f64, -0.0f64)).unwrap(),
+        r#"["0.12","0.38","1.00","0.00","0.00"]"#
+    );
+    assert_eq!(
+        write(FloatPolicy::DecimalString { precision: 0 }, &2.5f64).unwrap(),
+        r#""2""#
+    );
+
+    let integral = FloatPolicy::Integral;
+    assert_eq!(write(integral, &(3.0f64, -0.0f32)).unwrap(), "[3,0]");
+    match write(integral, &1.5f64).unwrap_err() {
+        Error::Canonical { kind, .. } => assert_eq!(kind, ErrorKind::Float),
+        err => panic!("unexpected error: {}", err),
+    }
+    match write(integral, &1e40f64).unwrap_err() {
+        Error::Canonical { kind, .. } => assert_eq!(kind, ErrorKind::Float),
+        err => panic!("unexpected error: {}", err),
+    }
+
+    for policy in &[FloatPolicy::Reject, integral, shortest, cents] {
+        let mut rejected = vec![f64::NAN, f64::INFINITY, f64::NEG_INFINITY];
+        if *policy == FloatPolicy::Reject {
+            rejected.extend(&[0.0, 3.0, 1.5]);
+        }
+        for v in rejected {
+            match write(*policy, &v).unwrap_err() {
+                Error::Canonical { kind, .. } if v.is_finite() => {
+                    assert_eq!(kind, ErrorKind::Float)
+                }
+                Error::Canonical { kind, .. } => assert_eq!(kind, ErrorKind::NonFinite),
+                err => panic!("unexpected error: {}", err),
+            }
+        }
+    }
+
+    // the policy reaches nested values and can be overridden under JCS
+    assert_eq!(
+        write(cents, &treemap!("b" => vec![0.5f64], "a" => vec![])).unwrap(),
+        r#"{"a":[],"b":["0.50"]}"#
+    );
+    let jcs_integral = |v: f64| {
+        let mut out = Vec::new();
+        let mut ser = Serializer::with_scheme(&mut out, Scheme::Jcs).float_policy(integral);
+        serde::Serialize::serialize(&v, &mut ser).map(|()| String::from_utf8(out).unwrap())
+    };
+    assert_eq!(jcs_integral(3.0).unwrap(), "3");
+    assert!(jcs_integral(1.5).is_err());
+}
+
+// Arrays nested `.0` levels deep, serialized without building them first.
+struct Nested(usize);
+
+impl serde::Serialize for Nested {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeSeq;
+        let mut seq = serializer.serialize_seq(Some(1))?;
+        if self.0 > 1 {
+            seq.serialize_element(&Nested(self.0 - 1))?;
+        }
+        seq.end()
+    }
+}
+
+#[test]
+fn write_limits() {
+    use crate::limits::Limits;
+
+    fn limited<T: serde::Serialize>(limits: Limits, value: &T) -> Result<String, Error> {
+        let mut out = Vec::new();
+        value.serialize(&mut crate::ser::Serializer::new(&mut out).limits(limits))?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    fn limited_err<T: serde::Serialize>(limits: Limits, value: &T) -> ErrorKind {
+        match limited(limits, value).unwrap_err() {
+            Error::Canonical { kind, .. } => kind,
+            err => panic!("unexpected error: {}", err),
+        }
+    }
+
+    let depth = Limits::default().max_depth(3);
+    assert_eq!(limited(depth, &Nested(3)).unwrap(), "[[[]]]");
+    assert_eq!(limited_err(depth, &Nested(4)), ErrorKind::TooDeep);
+    // far beyond what the stack could take without the limit
+    assert_eq!(limited_err(depth, &Nested(100_000)), ErrorKind::TooDeep);
+    assert_eq!(
+        limited_err(depth, &treemap!("a" => treemap!("b" => vec![vec![1]]))),
+        ErrorKind::TooDeep
+    );
+    #[derive(Serialize)]
+    enum E {
+        V(Vec<Vec<u8>>),
+    }
+    assert_eq!(
+        limited(depth, &E::V(vec![vec![1]])).unwrap(),
+        r#"{"V":[[1]]}"#
+    );
+    assert_eq!(
+        limited_err(depth, &vec![E::V(vec![vec![1]])]),
+        ErrorKind::TooDeep
+    );
+    assert_eq!(
+        limited(depth, &vec![E::V(vec![])]).unwrap(),
+        r#"[{"V":[]}]"#
+    );
+
+    // members held back for sorting count as well
+    let map = treemap!("b" => vec![], "a" => vec!["x".repeat(100)]);
+    let len = to_string(&map).unwrap().len();
+    let output = Limits::default().max_output_bytes(len);
+    assert_eq!(limited(output, &map).unwrap().len(), len);
+    let output = Limits::default().max_output_bytes(len - 1);
+    assert_eq!(limited_err(output, &map), ErrorKind::OutputTooLarge);
+    let output = Limits::default().max_output_bytes(50);
+    assert_eq!(limited_err(output, &map), ErrorKind::OutputTooLarge);
+    assert_eq!(
+        limited_err(output, &"x".repeat(100)),
+        ErrorKind::OutputTooLarge
+    );
+
+    let string = Limits::default().max_string_len(3);
+    assert_eq!(limited(string, &"abc").unwrap(), r#""abc""#);
+    assert_eq!(limited_err(string, &"abcd"), ErrorKind::StringTooLong);
+    assert_eq!(
+        limited_err(string, &treemap!("abcd" => 0)),
+        ErrorKind::StringTooLong
+    );
+
+    let keys = Limits::default().max_keys(2);
+    assert_eq!(
+        limited(
+            keys,
+            &treemap!("a" => treemap!("c" => 0, "d" => 0), "b" => treemap!())
+        )
+        .unwrap(),
+        r#"{"a":{"c":0,"d":0},"b":{}}"#
+    );
+    assert_eq!(
+        limited_err(keys, &treemap!("a" => 0, "b" => 0, "c" => 0)),
+        ErrorKind::TooManyKeys
+    );
+}
+
+#[test]
+fn read_limits() {
+    use crate::limits::Limits;
+
+    fn limited(limits: Limits, input: &str) -> Result<Value, Error> {
+        let mut de = de::Deserializer::from_slice(input.as_bytes()).limits(limits);
+        let value = serde::Deserialize::deserialize(&mut de)?;
+        de.end()?;
+        Ok(value)
+    }
+
+    fn limited_err(limits: Limits, input: &str) -> (ErrorKind, usize) {
+        match limited(limits, input).unwrap_err() {
+            Error::Parse { kind, offset } => (kind, offset),
+            err => panic!("unexpected error: {}", err),
+        }
+    }
+
+    let depth = Limits::default().max_depth(2);
+    assert_eq!(limited_err(depth, r#"[{"a":[]}]"#), (ErrorKind::TooDeep, 6));
+    assert_eq!(
+        limited(depth, r#"{"a":[1]}"#).unwrap(),
+        serde_json::json!({"a": [1]})
+    );
+    let hostile = "[".repeat(100_000);
+    assert_eq!(limited_err(depth, &hostile), (ErrorKind::TooDeep, 2));
+
+    let string = Limits::default().max_string_len(3);
+    assert_eq!(
+        limited(string, r#"["a\\c"]"#).unwrap(),
+        serde_json::json!(["a\\c"])
+    );
+    assert_eq!(
+        limited_err(string, r#"["abcd"]"#),
+        (ErrorKind::StringTooLong, 1)
+    );
+    assert_eq!(
+        limited_err(string, r#"{"abcd":1}"#),
+        (ErrorKind::StringTooLong, 1)
+    );
+
+    let keys = Limits::default().max_keys(1);
+    assert_eq!(
+        limited_err(keys, r#"{"a":1,"b":2}"#),
+        (ErrorKind::TooManyKeys, 7)
+    );
+}
+
+#[test]
+fn read_depth_is_capped_by_default() {
+    use crate::limits::{Limits, DEFAULT_MAX_DEPTH};
+
+    let hostile = "[".repeat(200_000);
+    for result in &[
+        de::from_str::<Value>(&hostile),
+        de::from_reader::<_, Value>(hostile.as_bytes()),
+    ] {
+        match result {
+            Err(Error::Parse { kind, offset }) => {
+                assert_eq!((*kind, *offset), (ErrorKind::TooDeep, DEFAULT_MAX_DEPTH))
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    let deepest = "[".repeat(DEFAULT_MAX_DEPTH) + &"]".repeat(DEFAULT_MAX_DEPTH);
+    de::from_str::<Value>(&deepest).unwrap();
+    let err = de::from_str_with_limits::<Value>(&deepest, Limits::default().max_depth(4));
+    assert_eq!(err.unwrap_err().kind(), Some(ErrorKind::TooDeep));
+    let err = de::from_slice_with_limits::<Value>(b"[\"abcd\"]", Limits::none().max_string_len(2));
+    assert_eq!(err.unwrap_err().kind(), Some(ErrorKind::StringTooLong));
+}
+
+#[test]
+fn canonicalize_limits() {
+    use crate::limits::Limits;
+
+    let limits = Limits::default().max_depth(8);
+    let mut out = Vec::new();
+    stream::canonicalize_with_limits(&b"[[[{\"b\":1,\"a\":2}]]]"[..], &mut out, limits).unwrap();
+    assert_eq!(out, br#"[[[{"a":2,"b":1}]]]"#);
+
+    let hostile = "[".repeat(100_000);
+    match stream::canonicalize_with_limits(hostile.as_bytes(), std::io::sink(), limits).unwrap_err()
+    {
+        Error::Canonical { kind, .. } => assert_eq!(kind, ErrorKind::TooDeep),
+        err => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn canonicalize_flushes_writer() {
+    use std::io::{self, BufWriter, Write};
+
+    struct FailingFlush;
+
+    impl Write for FailingFlush {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Err(io::Error::other("disk full"))
+        }
+    }
+
+    let err = stream::canonicalize(&b"[1]"[..], BufWriter::new(FailingFlush)).unwrap_err();
+    assert_eq!(err.kind(), Some(ErrorKind::Io));
+}
+
+#[test]
+fn write_error_pointers() {
+    fn error(result: Result<String, Error>) -> (ErrorKind, String) {
+        let err = result.unwrap_err();
+        (err.kind().unwrap(), err.pointer().unwrap().to_string())
+    }
+
+    #[derive(Serialize)]
+    enum E {
+        Newtype(f64),
+        Tuple(u8, f64),
+        Struct { a: Vec<f64> },
+    }
+
+    let value = treemap!("a" => vec![treemap!()], "b/c~" => vec![treemap!("x" => 1.5)]);
+    assert_eq!(
+        error(to_string(&value)),
+        (ErrorKind::Float, "/b~1c~0/0/x".to_string())
+    );
+    assert_eq!(
+        error(to_string(&f64::NAN)),
+        (ErrorKind::NonFinite, String::new())
+    );
+    assert_eq!(
+        error(to_string(&vec![E::Newtype(0.5)])),
+        (ErrorKind::Float, "/0/Newtype".to_string())
+    );
+    assert_eq!(
+        error(to_string(&E::Tuple(1, 0.5))),
+        (ErrorKind::Float, "/Tuple/1".to_string())
+    );
+    assert_eq!(
+        error(to_string(&E::Struct { a: vec![1.0, 0.5] })),
+        (ErrorKind::Float, "/Struct/a/1".to_string())
+    );
+    assert_eq!(
+        error(to_string(&treemap!("k" => treemap!(vec![1] => 0)))),
+        (ErrorKind::NonStringKey, "/k".to_string())
+    );
+    #[derive(Serialize, PartialEq, Eq, PartialOrd, Ord)]
+    #[serde(untagged)]
+    enum Key {
+        Int(u8),
+        Str(&'static str),
+    }
+    let value = treemap!("a" => treemap!(Key::Int(1) => 0, Key::Str("1") => 1));
+    let mut out = Vec::new();
+    let result = serde::Serialize::serialize(
+        &value,
+        &mut crate::ser::Serializer::new(&mut out).stringify_keys(true),
+    )
+    .map(|()| String::new());
+    assert_eq!(error(result), (ErrorKind::DuplicateKey, "/a/1".to_string()));
+    assert_eq!(
+        error(jcs::to_string(&treemap!("k" => vec![f64::INFINITY]))),
+        (ErrorKind::NonFinite, "/k/0".to_string())
+    );
+
+    // errors that carry a pointer say where they happened
+    assert_eq!(
+        to_string(&treemap!("a" => 1.5)).unwrap_err().to_string(),
+        r#"error: floating point number: 1.5 at "/a""#
+    );
+    assert_eq!(
+        to_string(&1.5).unwrap_err().to_string(),
+        "error: floating point number: 1.5"
+    );
+
+    // pointers also survive the streaming canonicalizer
+    let err = stream::canonicalize(&br#"{"a":[0,{"b":1.5}]}"#[..], Vec::new()).unwrap_err();
+    assert_eq!(err.pointer(), Some("/a/1/b"));
+
+    let err = de::from_str::<Value>("[1.5]").unwrap_err();
+    assert_eq!((err.kind(), err.pointer()), (Some(ErrorKind::Float), None));
+    let err = Error::from(std::io::Error::other("disk full"));
+    assert_eq!(err.kind(), Some(ErrorKind::Io));
+}
+
+#[cfg(feature = "sha2")]
+#[test]
+fn digest_matches_to_vec() {
+    use crate::hash::{to_digest, Digest, HashWriter, Sha256, Sha512};
+    use crate::ser::{Scheme, Serializer};
+
+    #[derive(Serialize)]
+    struct Doc {
+        z: Vec<u64>,
+        a: BTreeMap<String, Option<bool>>,
+        s: &'static str,
+    }
+    let large = Doc {
+        z: (0..10_000).collect(),
+        a: (0..1_000)
+            .map(|i| (format!("k{}", i), Some(i % 2 == 0)))
+            .collect(),
+        s: "quote \" backslash \\ é",
+    };
+    let bytes = crate::ser::to_vec(&large).unwrap();
+    assert_eq!(
+        to_digest::<Sha256, _>(&large).unwrap(),
+        Sha256::digest(&bytes)
+    );
+    assert_eq!(
+        to_digest::<Sha512, _>(&large).unwrap(),
+        Sha512::digest(&bytes)
+    );
+
+    for value in &[
+        serde_json::json!(null),
+        serde_json::json!({"b": [1, {}], "a": "x"}),
+    ] {
+        let bytes = crate::ser::to_vec(value).unwrap();
+        assert_eq!(
+            to_digest::<Sha256, _>(value).unwrap(),
+            Sha256::digest(&bytes)
+        );
+    }
+    assert_eq!(
+        format!(
+            "{:x}",
+            to_digest::<Sha256, _>(&treemap!("b" => 2, "a" => 1)).unwrap()
+        ),
+        // sha256 of `{"a":1,"b":2}`
+        "43258cff783fe7036d8a43033f830adfc60ec037382473548ac742b888292777"
+    );
+
+    // any serializer configuration can write into a hash
+    let mut ser = Serializer::with_scheme(HashWriter::<Sha256>::new(), Scheme::Jcs);
+    serde::Serialize::serialize(&(1.5f64, "a"), &mut ser).unwrap();
+    assert_eq!(
+        ser.into_inner().finalize(),
+        Sha256::digest(jcs::to_vec(&(1.5f64, "a")).unwrap())
+    );
+
+    assert_eq!(
+        to_digest::<Sha256, _>(&treemap!("a" => vec![1.5]))
+            .unwrap_err()
+            .pointer(),
+        Some("/a/0")
+    );
+}
+
+#[cfg(feature = "blake3")]
+#[test]
+fn digest_blake3() {
+    use crate::hash::{to_digest, Blake3};
+
+    let value = treemap!("b" => vec![1, 2], "a" => vec![]);
+    let bytes = crate::ser::to_vec(&value).unwrap();
+    assert_eq!(
+        to_digest::<Blake3, _>(&value).unwrap().as_slice(),
+        blake3::hash(&bytes).as_bytes()
+    );
+}
+
+#[cfg(feature = "sha2")]
+#[test]
+fn canonical_digest() {
+    use crate::hash::{Algorithm, CanonicalDigest};
+
+    let value = treemap!("b" => 2, "a" => 1);
+    let digest = CanonicalDigest::new(Algorithm::Sha256, &value).unwrap();
+    let text = "sha256:43258cff783fe7036d8a43033f830adfc60ec037382473548ac742b888292777";
+    assert_eq!(digest.to_string(), text);
+    assert_eq!(text.parse::<CanonicalDigest>().unwrap(), digest);
+    assert_eq!(digest.algorithm(), Algorithm::Sha256);
+    assert_eq!(digest.as_bytes().len(), 32);
+
+    // the same document built in another order verifies, a different one does not
+    let reordered = serde_json::json!({"a": 1, "b": 2});
+    assert!(digest.verify(&reordered).unwrap());
+    assert!(!digest.verify(&treemap!("a" => 1, "b" => 3)).unwrap());
+    assert!(digest.verify(&1.5f64).is_err());
+
+    let sha512 = CanonicalDigest::new(Algorithm::Sha512, &value).unwrap();
+    assert!(sha512.to_string().starts_with("sha512:"));
+    assert_eq!(sha512.to_string().len(), "sha512:".len() + 128);
+    assert_eq!(
+        sha512.to_string().parse::<CanonicalDigest>().unwrap(),
+        sha512
+    );
+    assert_ne!(sha512, digest);
+
+    // serialized as its string, including inside canonical documents
+    let doc = treemap!("digest" => digest.clone());
+    let json = to_string(&doc).unwrap();
+    assert_eq!(json, format!(r#"{{"digest":"{}"}}"#, text));
+    let back: BTreeMap<String, CanonicalDigest> = de::from_str(&json).unwrap();
+    assert_eq!(back["digest"], digest);
+    assert!(serde_json::from_str::<CanonicalDigest>(r#""sha256:00""#).is_err());
+
+    for (input, offset) in &[
+        ("43258cff", 8),
+        ("md5:43258cff783fe7036d8a43033f830adf", 0),
+        ("sha256:43258cff", 7),
+        (
+            "sha256:43258CFF783fe7036d8a43033f830adfc60ec037382473548ac742b888292777",
+            12,
+        ),
+        (
+            "sha256:43258cff783fe7036d8a43033f830adfc60ec037382473548ac742b88829277g",
+            70,
+        ),
+    ] {
+        match input.parse::<CanonicalDigest>().unwrap_err() {
+            Error::Parse { kind, offset: at } => {
+                assert_eq!((kind, at), (ErrorKind::InvalidDigest, *offset), "{}", input)
+            }
+            err => panic!("unexpected error: {}", err),
+        }
+    }
+}
+
+#[cfg(feature = "sha2")]
+fn tuf_root() -> crate::tuf::Signed<Value> {
+    serde_json::from_str(include_str!("../testdata/tuf/root.json")).unwrap()
+}
+
+#[cfg(feature = "sha2")]
+#[test]
+fn tuf_envelope_round_trip() {
+    use crate::tuf::{PublicKey, Signed};
+
+    let root = tuf_root();
+    assert_eq!(root.signatures.len(), 2);
+    let expected = to_string(
+        &serde_json::from_str::<Value>(include_str!("../testdata/tuf/root.json")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(to_string(&root).unwrap(), expected);
+    let back: Signed<Value> = de::from_str(&expected).unwrap();
+    assert_eq!(back, root);
+
+    // key IDs are computed the way the metadata lists them
+    let keys: BTreeMap<String, PublicKey> =
+        serde_json::from_value(root.signed["keys"].clone()).unwrap();
+    for (keyid, key) in &keys {
+        assert_eq!(&key.key_id().unwrap(), keyid);
+    }
+
+    // members beyond the standard ones take part in the key ID
+    let mut key = keys.values().next().unwrap().clone();
+    let keyid = key.key_id().unwrap();
+    key.extra.insert(
+        "keyid_hash_algorithms".to_string(),
+        serde_json::json!(["sha256"]),
+    );
+    assert_ne!(key.key_id().unwrap(), keyid);
+    assert!(to_string(&key)
+        .unwrap()
+        .contains(r#""keyid_hash_algorithms":["sha256"]"#));
+}
+
+#[cfg(feature = "ed25519")]
+#[test]
+fn tuf_sign_and_verify() {
+    use crate::tuf::{PublicKey, Signed, Signer};
+    use ed25519_dalek::SigningKey;
+
+    let root = tuf_root();
+    let keys: BTreeMap<String, PublicKey> =
+        serde_json::from_value(root.signed["keys"].clone()).unwrap();
+    let threshold = root.signed["roles"]["root"]["threshold"].as_u64().unwrap() as usize;
+
+    // the fixture was signed independently by two of its three keys
+    root.verify(&keys, threshold).unwrap();
+    assert_eq!(
+        root.verify(&keys, 3).unwrap_err().kind(),
+        Some(ErrorKind::SignatureThreshold)
+    );
+    assert!(root.verify(&keys, 0).is_err());
+
+    // Ed25519 is deterministic, so re-signing reproduces the fixture
+    let signers: Vec<_> = (1..=3u8)
+        .map(|i| SigningKey::from_bytes(&[i; 32]))
+        .collect();
+    let mut resigned = Signed::new(root.signed.clone());
+    resigned.sign(&signers[1]).unwrap();
+    resigned.sign(&signers[0]).unwrap();
+    assert_eq!(resigned, root);
+    // signing again with the same key replaces its signature
+    resigned.sign(&signers[0]).unwrap();
+    assert_eq!(resigned.signatures.len(), 2);
+    resigned.sign(&signers[2]).unwrap();
+    resigned.verify(&keys, 3).unwrap();
+
+    // any change to the payload invalidates every signature
+    let mut tampered = root.clone();
+    tampered.signed["version"] = serde_json::json!(2);
+    assert!(tampered.verify(&keys, 1).is_err());
+
+    // a key signing twice counts once, unknown keys do not count
+    let mut doubled = root.clone();
+    doubled.signatures = vec![root.signatures[0].clone(), root.signatures[0].clone()];
+    assert!(doubled.verify(&keys, 2).is_err());
+    let stranger = SigningKey::from_bytes(&[9; 32]);
+    let mut foreign = Signed::new(root.signed.clone());
+    foreign.sign(&stranger).unwrap();
+    foreign.sign(&signers[0]).unwrap();
+    assert!(foreign.verify(&keys, 2).is_err());
+    assert_eq!(keys.get(&stranger.public_key().key_id().unwrap()), None);
+
+    // one key listed under a second key ID still counts once
+    let mut aliased = keys.clone();
+    let first = &root.signatures[0];
+    aliased.insert("alias".to_string(), keys[&first.keyid].clone());
+    let mut twice = root.clone();
+    twice.signatures = vec![
+        first.clone(),
+        crate::tuf::Signature {
+            keyid: "alias".to_string(),
+            sig: first.sig.clone(),
+        },
+    ];
+    assert_eq!(
+        twice.verify(&aliased, 2).unwrap_err().kind(),
+        Some(ErrorKind::SignatureThreshold)
+    );
+    twice.verify(&aliased, 1).unwrap();
+}
+
+// Vectors written by scripts/securesystemslib_vectors.py.
+#[derive(Deserialize)]
+struct SecuresystemslibVectors {
+    documents: Vec<SecuresystemslibDocument>,
+    rejected: Vec<String>,
+    #[cfg_attr(not(feature = "sha2"), allow(dead_code))]
+    keys: Vec<SecuresystemslibKey>,
+}
+
+#[derive(Deserialize)]
+struct SecuresystemslibDocument {
+    input: String,
+    canonical: String,
+}
+
+#[cfg_attr(not(feature = "sha2"), allow(dead_code))]
+#[derive(Deserialize)]
+struct SecuresystemslibKey {
+    keytype: String,
+    scheme: String,
+    public: String,
+    keyid_sha256: String,
+    keyid_sha512: String,
+}
+
+fn securesystemslib_vectors() -> SecuresystemslibVectors {
+    serde_json::from_str(include_str!("../testdata/securesystemslib/vectors.json")).unwrap()
+}
+
+#[test]
+fn securesystemslib_encode_canonical() {
+    use crate::securesystemslib;
+
+    let vectors = securesystemslib_vectors();
+    for document in &vectors.documents {
+        let value: Value = serde_json::from_str(&document.input).unwrap();
+        assert_eq!(
+            securesystemslib::to_string(&value).unwrap(),
+            document.canonical,
+            "input: {}",
+            document.input
+        );
+    }
+    for input in &vectors.rejected {
+        let value: Value = serde_json::from_str(input).unwrap();
+        assert_eq!(
+            securesystemslib::to_string(&value).unwrap_err().kind(),
+            Some(ErrorKind::Float),
+            "input: {}",
+            input
+        );
+    }
+
+    // integral floats are where the profile departs from `ser`
+    assert_eq!(to_string(&1.0f64).unwrap(), "1");
+    assert!(securesystemslib::to_string(&1.0f64).is_err());
+    assert_eq!(
+        securesystemslib::to_string(&i128::MIN).unwrap(),
+        i128::MIN.to_string()
+    );
+    assert_eq!(
+        securesystemslib::to_string(&treemap!(1 => 0))
+            .unwrap_err()
+            .kind(),
+        Some(ErrorKind::NonStringKey)
+    );
+}
+
+#[cfg(feature = "sha2")]
+#[test]
+fn securesystemslib_key_ids() {
+    use crate::securesystemslib;
+    use crate::tuf::{KeyValue, PublicKey};
+
+    for vector in securesystemslib_vectors().keys {
+        let mut key = PublicKey {
+            keytype: vector.keytype,
+            scheme: vector.scheme,
+            keyval: KeyValue {
+                public: vector.public,
+            },
+            extra: Default::default(),
+        };
+        assert_eq!(securesystemslib::key_id(&key).unwrap(), vector.keyid_sha256);
+        assert_eq!(
+            securesystemslib::key_ids(&key).unwrap(),
+            vec![vector.keyid_sha256.clone(), vector.keyid_sha512]
+        );
+
+        // members securesystemslib does not hash are ignored, and a key that
+        // carries `keyid_hash_algorithms` gets the same ID from TUF
+        key.extra.insert("keyid".to_string(), "ignored".into());
+        assert_eq!(securesystemslib::key_id(&key).unwrap(), vector.keyid_sha256);
+        key.extra.clear();
+        key.extra.insert(
+            "keyid_hash_algorithms".to_string(),
+            serde_json::json!(["sha256", "sha512"]),
+        );
+        assert_eq!(key.key_id().unwrap(), vector.keyid_sha256);
+    }
+}
+
+#[cfg(feature = "hs256")]
+#[test]
+fn jws_rfc7797_vector() {
+    use crate::jws::{self, Form, SigningKey};
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+    // RFC 7797, section 4.2
+    let key = SigningKey::HS256(
+        URL_SAFE_NO_PAD
+            .decode("AyM1SysPpbyDfgZld3umj1qzKObwVMkoqQ-EstJQLr_T-1qS0gZH75aKtMN3Yj0iPS4hcgUuTwjAzZr1Z9CAow")
+            .unwrap(),
+    );
+    let protected = "eyJhbGciOiJIUzI1NiIsImI2NCI6ZmFsc2UsImNyaXQiOlsiYjY0Il19";
+    let signature = key.sign(&jws::signing_input(protected, b"$.02", false));
+    assert_eq!(
+        URL_SAFE_NO_PAD.encode(signature),
+        "A5dxf2s96_n5FLueVuW1Z_vh161FwXZC4YLPff6dmDY"
+    );
+
+    // the canonical header is byte for byte the one of the RFC
+    let jws = jws::sign_compact(&0, &key, Form::Unencoded).unwrap();
+    assert!(jws.starts_with(&format!("{}..", protected)));
+}
+
+#[cfg(any(feature = "hs256", feature = "es256", feature = "eddsa"))]
+fn jws_keys() -> Vec<crate::jws::SigningKey> {
+    use crate::jws::SigningKey;
+
+    vec![
+        #[cfg(feature = "hs256")]
+        SigningKey::HS256(b"a shared secret".to_vec()),
+        #[cfg(feature = "es256")]
+        SigningKey::ES256(p256::ecdsa::SigningKey::from_slice(&[7; 32]).unwrap()),
+        #[cfg(feature = "eddsa")]
+        SigningKey::EdDSA(ed25519_dalek::SigningKey::from_bytes(&[7; 32])),
+    ]
+}
+
+#[cfg(any(feature = "hs256", feature = "es256", feature = "eddsa"))]
+#[test]
+fn jws_sign_and_verify() {
+    use crate::jws::{self, Form};
+
+    let value = serde_json::json!({"b": [1, "é"], "a": {"y": null, "x": true}});
+    let other = serde_json::json!({"b": [1, "é"], "a": {"y": null, "x": false}});
+
+    let keys = jws_keys();
+    for key in &keys {
+        let public = key.verifying_key();
+
+        let compact = jws::sign_compact(&value, key, Form::Attached).unwrap();
+        let back: Value = jws::verify_compact(&compact, &public).unwrap();
+        assert_eq!(back, value);
+
+        for &form in &[Form::Detached, Form::Unencoded] {
+            let compact = jws::sign_compact(&value, key, form).unwrap();
+            jws::verify_compact_detached(&compact, &value, &public).unwrap();
+            assert_eq!(
+                jws::verify_compact_detached(&compact, &other, &public)
+                    .unwrap_err()
+                    .kind(),
+                Some(ErrorKind::BadSignature)
+            );
+            // a detached JWS carries no payload to return
+            assert_eq!(
+                jws::verify_compact::<Value>(&compact, &public)
+                    .unwrap_err()
+                    .kind(),
+                Some(ErrorKind::InvalidJws)
+            );
+        }
+    }
+
+    // one valid signature by the key is enough in the JSON serialization
+    let json = jws::sign_json(&value, &keys, Form::Attached).unwrap();
+    assert_eq!(json.signatures.len(), keys.len());
+    for key in &keys {
+        let back: Value = jws::verify_json(&json, &key.verifying_key()).unwrap();
+        assert_eq!(back, value);
+    }
+    let json = jws::sign_json(&value, &keys, Form::Unencoded).unwrap();
+    assert_eq!(json.payload, None);
+    for key in &keys {
+        jws::verify_json_detached(&json, &value, &key.verifying_key()).unwrap();
+        assert!(jws::verify_json_detached(&json, &other, &key.verifying_key()).is_err());
+    }
+
+    // a malformed entry does not stop the search for a valid one
+    let mut json = jws::sign_json(&value, &keys, Form::Attached).unwrap();
+    for protected in &["!!", "e30", "eyJhbGciOjF9"] {
+        json.signatures.insert(
+            0,
+            jws::JsonSignature {
+                protected: protected.to_string(),
+                signature: "AA".to_string(),
+            },
+        );
+    }
+    for key in &keys {
+        let back: Value = jws::verify_json(&json, &key.verifying_key()).unwrap();
+        assert_eq!(back, value);
+    }
+    json.signatures.truncate(3);
+    assert_eq!(
+        jws::verify_json::<Value>(&json, &keys[0].verifying_key())
+            .unwrap_err()
+            .kind(),
+        Some(ErrorKind::BadSignature)
+    );
+}
+
+#[cfg(any(feature = "hs256", feature = "es256", feature = "eddsa"))]
+#[test]
+fn jws_verification_recanonicalizes() {
+    use crate::jws::{self, Form};
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+    let value = serde_json::json!({"b": 1, "a": [2, 3]});
+    for key in &jws_keys() {
+        let public = key.verifying_key();
+        let compact = jws::sign_compact(&value, key, Form::Attached).unwrap();
+        let parts: Vec<&str> = compact.split('.').collect();
+
+        // the same object written differently still verifies
+        let reformatted = URL_SAFE_NO_PAD.encode("{ \"b\": 1, \"a\": [ 2, 3 ] }");
+        let jws = format!("{}.{}.{}", parts[0], reformatted, parts[2]);
+        let back: Value = jws::verify_compact(&jws, &public).unwrap();
+        assert_eq!(back, value);
+
+        // a different object does not
+        let changed = URL_SAFE_NO_PAD.encode(r#"{"a":[2,3],"b":2}"#);
+        let jws = format!("{}.{}.{}", parts[0], changed, parts[2]);
+        assert_eq!(
+            jws::verify_compact::<Value>(&jws, &public)
+                .unwrap_err()
+                .kind(),
+            Some(ErrorKind::BadSignature)
+        );
+
+        // unknown critical headers are refused
+        let header = URL_SAFE_NO_PAD.encode(format!(
+            r#"{{"alg":"{}","crit":["exp"],"exp":0}}"#,
+            key.algorithm().name()
+        ));
+        let jws = format!("{}.{}.{}", header, parts[1], parts[2]);
+        assert_eq!(
+            jws::verify_compact::<Value>(&jws, &public)
+                .unwrap_err()
+                .kind(),
+            Some(ErrorKind::InvalidJws)
+        );
+        assert!(jws::verify_compact::<Value>("a.b", &public).is_err());
+    }
+
+    // a signature never verifies with a key of another algorithm
+    let keys = jws_keys();
+    for (i, key) in keys.iter().enumerate() {
+        let compact = jws::sign_compact(&value, key, Form::Attached).unwrap();
+        for (j, other) in keys.iter().enumerate() {
+            let result = jws::verify_compact::<Value>(&compact, &other.verifying_key());
+            assert_eq!(result.is_ok(), i == j);
+        }
+    }
+}
+
+#[cfg(feature = "jwk")]
+#[test]
+fn jwk_thumbprints() {
+    use crate::jwk::{jwk_thumbprint, jwk_thumbprint_base64url, jwk_thumbprint_uri, Jwk};
+
+    // RFC 7638, section 3.1
+    let mut jwk: Jwk = serde_json::from_str(
+        r#"{
+            "kty": "RSA",
+            "n": "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw",
+            "e": "AQAB",
+            "alg": "RS256",
+            "kid": "2011-04-29"
+        }"#,
+    )
+    .unwrap();
+    let expected = [
+        55, 54, 203, 177, 120, 124, 184, 48, 156, 119, 238, 140, 55, 5, 197, 225, 111, 251, 158,
+        133, 151, 21, 144, 31, 30, 76, 89, 177, 17, 130, 245, 123,
+    ];
+    assert_eq!(jwk_thumbprint(&jwk).unwrap().as_slice(), &expected[..]);
+    assert_eq!(
+        jwk_thumbprint_base64url(&jwk).unwrap(),
+        "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs"
+    );
+    assert_eq!(
+        jwk_thumbprint_uri(&jwk).unwrap(),
+        "urn:ietf:params:oauth:jwk-thumbprint:sha-256:NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs"
+    );
+
+    // optional members do not take part
+    jwk.params.remove("kid");
+    jwk.params.insert("use".to_string(), "sig".into());
+    assert_eq!(
+        jwk_thumbprint_base64url(&jwk).unwrap(),
+        "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs"
+    );
+
+    // RFC 8037, appendix A.3
+    let okp: Jwk = serde_json::from_str(
+        r#"{"kty":"OKP","crv":"Ed25519","x":"11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo"}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        jwk_thumbprint_base64url(&okp).unwrap(),
+        "kPrK_qmxVWaYVA9wwBF6Iuo3vVzz7TxHCTwXBygrS4k"
+    );
+
+    for (input, pointer) in &[
+        (r#"{"kty":"RSA","n":"AQAB"}"#, "/e"),
+        (r#"{"kty":"EC","crv":"P-256","x":"AA"}"#, "/y"),
+        (r#"{"kty":"oct","k":5}"#, "/k"),
+        (r#"{"kty":"DSA"}"#, "/kty"),
+    ] {
+        let jwk: Jwk = serde_json::from_str(input).unwrap();
+        let err = jwk_thumbprint(&jwk).unwrap_err();
+        assert_eq!(err.kind(), Some(ErrorKind::InvalidJwk), "{}", input);
+        assert_eq!(err.pointer(), Some(*pointer), "{}", input);
+    }
+}
+
+#[test]
+fn matrix_canonical_json() {
+    use crate::matrix;
+
+    let value = serde_json::json!({
+        "\u{1f600}": 1,
+        "\u{e000}": "日本語\u{1}\n",
+        "a": [-9007199254740991i64, 9007199254740991i64],
+    });
+    // code point order, unlike the UTF-16 order of JCS
+    assert_eq!(
+        matrix::to_string(&value).unwrap(),
+        "{\"a\":[-9007199254740991,9007199254740991],\"\u{e000}\":\"日本語\\u0001\\n\",\"\u{1f600}\":1}"
+    );
+
+    for value in &[
+        serde_json::json!({"a": 9007199254740992i64}),
+        serde_json::json!([-9007199254740992i64]),
+        serde_json::json!(u64::MAX),
+    ] {
+        assert_eq!(
+            matrix::to_string(value).unwrap_err().kind(),
+            Some(ErrorKind::NumberOutOfRange)
+        );
+    }
+    assert_eq!(
+        matrix::to_string(&serde_json::json!({"a": 1.0}))
+            .unwrap_err()
+            .kind(),
+        Some(ErrorKind::Float)
+    );
+
+    // signatures and unsigned data are not signed
+    let object = serde_json::json!({
+        "b": 1,
+        "signatures": {"example.org": {}},
+        "unsigned": {"age": 5},
+    });
+    assert_eq!(matrix::signable_bytes(&object).unwrap(), br#"{"b":1}"#);
+    assert_eq!(
+        matrix::signable_bytes(&serde_json::json!([]))
+            .unwrap_err()
+            .kind(),
+        Some(ErrorKind::NotAnObject)
+    );
+}
+
+#[test]
+fn matrix_redaction() {
+    use crate::matrix::{redact, RoomVersion};
+
+    let member = serde_json::json!({
+        "type": "m.room.member",
+        "event_id": "$a",
+        "room_id": "!r:example.org",
+        "sender": "@u:example.org",
+        "state_key": "@u:example.org",
+        "origin": "example.org",
+        "membership": "join",
+        "unsigned": {"age": 1},
+        "extra": true,
+        "content": {
+            "membership": "join",
+            "displayname": "U",
+            "join_authorised_via_users_server": "@v:example.org",
+            "third_party_invite": {"display_name": "x", "signed": {"token": "t"}},
+        },
+    });
+    assert_eq!(
+        redact(&member, RoomVersion::V1).unwrap(),
+        serde_json::json!({
+            "type": "m.room.member",
+            "event_id": "$a",
+            "room_id": "!r:example.org",
+            "sender": "@u:example.org",
+            "state_key": "@u:example.org",
+            "origin": "example.org",
+            "membership": "join",
+            "content": {"membership": "join"},
+        })
+    );
+    assert_eq!(
+        redact(&member, RoomVersion::V9).unwrap()["content"],
+        serde_json::json!({
+            "membership": "join",
+            "join_authorised_via_users_server": "@v:example.org",
+        })
+    );
+    let v11 = redact(&member, RoomVersion::V11).unwrap();
+    assert_eq!(v11.get("origin"), None);
+    assert_eq!(v11.get("membership"), None);
+    assert_eq!(
+        v11["content"]["third_party_invite"],
+        serde_json::json!({"signed": {"token": "t"}})
+    );
+
+    let create = serde_json::json!({
+        "type": "m.room.create",
+        "content": {"creator": "@u:example.org", "room_version": "11"},
+    });
+    assert_eq!(
+        redact(&create, RoomVersion::V10).unwrap()["content"],
+        serde_json::json!({"creator": "@u:example.org"})
+    );
+    assert_eq!(
+        redact(&create, RoomVersion::V11).unwrap()["content"],
+        create["content"]
+    );
+
+    let join_rules = serde_json::json!({
+        "type": "m.room.join_rules",
+        "content": {"join_rule": "restricted", "allow": []},
+    });
+    assert_eq!(
+        redact(&join_rules, RoomVersion::V7).unwrap()["content"],
+        serde_json::json!({"join_rule": "restricted"})
+    );
+    assert_eq!(
+        redact(&join_rules, RoomVersion::V8).unwrap()["content"],
+        join_rules["content"]
+    );
+
+    let aliases = serde_json::json!({"type": "m.room.aliases", "content": {"aliases": []}});
+    assert_eq!(
+        redact(&aliases, RoomVersion::V5).unwrap()["content"],
+        aliases["content"]
+    );
+    assert_eq!(
+        redact(&aliases, RoomVersion::V6).unwrap()["content"],
+        serde_json::json!({})
+    );
+
+    // events without content get an empty one
+    assert_eq!(
+        redact(
+            &serde_json::json!({"type": "m.room.message"}),
+            RoomVersion::V4
+        )
+        .unwrap(),
+        serde_json::json!({"type": "m.room.message", "content": {}})
+    );
+    assert_eq!(RoomVersion::from_id("10"), Some(RoomVersion::V10));
+    assert_eq!(RoomVersion::from_id("org.example.custom"), None);
+}
+
+// The examples of the Matrix specification, appendix "Signing details".
+#[cfg(feature = "matrix")]
+#[test]
+fn matrix_signing_vectors() {
+    use crate::matrix::{self, RoomVersion};
+    use base64::{
+        alphabet,
+        engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+        Engine,
+    };
+    use ed25519_dalek::SigningKey;
+    use std::convert::TryFrom;
+
+    // the seed has nonzero trailing bits, which Python ignores
+    let lenient = GeneralPurpose::new(
+        &alphabet::STANDARD,
+        GeneralPurposeConfig::new()
+            .with_decode_padding_mode(DecodePaddingMode::RequireNone)
+            .with_decode_allow_trailing_bits(true),
+    );
+    let seed = lenient
+        .decode("YJDBA9Xnr2sVqXD9Vj7XVUnmFZcZrlw8Md7kMW+3XA1")
+        .unwrap();
+    let key = SigningKey::from_bytes(&<[u8; 32]>::try_from(seed.as_slice()).unwrap());
+    let public = key.verifying_key();
+
+    for (object, signature) in &[
+        (
+            serde_json::json!({}),
+            "K8280/U9SSy9IVtjBuVeLr+HpOB4BQFWbg+UZaADMtTdGYI7Geitb76LTrr5QV/7Xg4ahLwYGYZzuHGZKM5ZAQ",
+        ),
+        (
+            serde_json::json!({"one": 1, "two": "Two"}),
+            "KqmLSbO39/Bzb0QIYE82zqLwsA+PDzYIpIRA2sRQ4sL53+sN6/fpNSoqE7BP7vBZhG6kYdD13EIMJpvhJI+6Bw",
+        ),
+    ] {
+        let mut signed = object.clone();
+        matrix::sign_json(&mut signed, "domain", "ed25519:1", &key).unwrap();
+        assert_eq!(signed["signatures"]["domain"]["ed25519:1"], *signature);
+        matrix::verify_json(&signed, "domain", "ed25519:1", &public).unwrap();
+        assert!(matrix::verify_json(&signed, "domain", "ed25519:2", &public).is_err());
+
+        // unsigned data may change without breaking the signature
+        signed["unsigned"] = serde_json::json!({"age": 1});
+        matrix::verify_json(&signed, "domain", "ed25519:1", &public).unwrap();
+        signed["extra"] = serde_json::json!(1);
+        assert_eq!(
+            matrix::verify_json(&signed, "domain", "ed25519:1", &public)
+                .unwrap_err()
+                .kind(),
+            Some(ErrorKind::BadSignature)
+        );
+    }
+
+    let mut event = serde_json::json!({
+        "auth_events": [],
+        "content": {},
+        "depth": 3,
+        "origin": "domain",
+        "origin_server_ts": 1000000,
+        "prev_events": [],
+        "room_id": "!x:domain",
+        "sender": "@a:domain",
+        "type": "X",
+        "unsigned": {"age_ts": 1000000},
+    });
+    matrix::sign_event(&mut event, RoomVersion::V1, "domain", "ed25519:1", &key).unwrap();
+    assert_eq!(
+        event["hashes"]["sha256"],
+        "5jM4wQpv6lnBo7CLIghJuHdW+s2CMBJPUOGOC89ncos"
+    );
+    assert_eq!(
+        event["signatures"]["domain"]["ed25519:1"],
+        "KxwGjPSDEtvnFgU00fwFz+l6d2pJM6XBIaMEn81SXPTRl16AqLAYqfIReFGZlHi5KLjAWbOoMszkwsQma+lYAg"
+    );
+    matrix::verify_content_hash(&event).unwrap();
+    matrix::verify_event(&event, RoomVersion::V1, "domain", "ed25519:1", &public).unwrap();
+
+    // changed content breaks the content hash but not the signature,
+    // which covers the redacted event only
+    event["content"]["body"] = serde_json::json!("changed");
+    assert_eq!(
+        matrix::verify_content_hash(&event).unwrap_err().kind(),
+        Some(ErrorKind::ContentHashMismatch)
+    );
+    matrix::verify_event(&event, RoomVersion::V1, "domain", "ed25519:1", &public).unwrap();
+    event["depth"] = serde_json::json!(4);
+    assert!(matrix::verify_event(&event, RoomVersion::V1, "domain", "ed25519:1", &public).is_err());
+}
+
+#[test]
+fn projection_exclude() {
+    use crate::{projection::Projection, ser::to_vec_with};
+
+    #[derive(Serialize)]
+    struct Metadata {
+        name: &'static str,
+        #[serde(rename = "updatedAt")]
+        updated_at: u64,
+    }
+
+    #[derive(Serialize)]
+    struct Record {
+        metadata: Metadata,
+        signatures: Vec<&'static str>,
+        items: Vec<Value>,
+    }
+
+    let record = Record {
+        metadata: Metadata {
+            name: "a",
+            updated_at: 1_600_000_000,
+        },
+        signatures: vec!["sig"],
+        items: vec![
+            serde_json::json!({"id": 1, "secret": "x"}),
+            serde_json::json!({"id": 2, "secret": "y"}),
+        ],
+    };
+
+    let projection =
+        Projection::exclude(&["/signatures", "/metadata/updatedAt", "/items/*/secret"]).unwrap();
+    assert_eq!(
+        to_vec_with(&record, &projection).unwrap(),
+        br#"{"items":[{"id":1},{"id":2}],"metadata":{"name":"a"}}"#.to_vec()
+    );
+
+    // array elements can be left out by index
+    let projection = Projection::exclude(&["/1"]).unwrap();
+    assert_eq!(
+        to_vec_with(&record.items, &projection).unwrap(),
+        br#"[{"id":1,"secret":"x"}]"#.to_vec()
+    );
+
+    // required paths must be there, optional ones need not
+    let err = to_vec_with(
+        &record,
+        &Projection::exclude(&["/metadata/createdAt"]).unwrap(),
+    )
+    .unwrap_err();
+    assert_eq!(err.kind(), Some(ErrorKind::MissingPath));
+    assert_eq!(err.pointer(), Some("/metadata/createdAt"));
+    let projection = Projection::exclude(&["/updatedAt"])
+        .unwrap()
+        .optional(&["/createdAt"])
+        .unwrap();
+    assert_eq!(
+        to_vec_with(&record.metadata, &projection).unwrap(),
+        br#"{"name":"a"}"#.to_vec()
+    );
+}
+
+#[test]
+fn projection_include() {
+    use crate::{projection::Projection, ser::to_vec_with};
+
+    let value = serde_json::json!({
+        "kind": "order",
+        "lines": [
+            {"sku": "a~b", "qty": 1, "note": "n"},
+            {"sku": "c/d", "qty": 2},
+        ],
+        "customer": {"id": 7, "email": "e"},
+    });
+
+    let projection = Projection::include(&["/kind", "/lines/*/qty", "/customer"]).unwrap();
+    assert_eq!(
+        String::from_utf8(to_vec_with(&value, &projection).unwrap()).unwrap(),
+        r#"{"customer":{"email":"e","id":7},"kind":"order","lines":[{"qty":1},{"qty":2}]}"#
+    );
+    let projection = Projection::include(&["/lines/1"]).unwrap();
+    assert_eq!(
+        String::from_utf8(to_vec_with(&value, &projection).unwrap()).unwrap(),
+        r#"{"lines":[{"qty":2,"sku":"c/d"}]}"#
+    );
+
+    // a wildcard path has to match below every element
+    let err = to_vec_with(&value, &Projection::include(&["/lines/*/note"]).unwrap()).unwrap_err();
+    assert_eq!(err.kind(), Some(ErrorKind::MissingPath));
+    assert_eq!(err.pointer(), Some("/lines/1/note"));
+    let projection = Projection::include(&["/kind"])
+        .unwrap()
+        .optional(&["/lines/*/note"])
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(to_vec_with(&value, &projection).unwrap()).unwrap(),
+        r#"{"kind":"order","lines":[{"note":"n"},{}]}"#
+    );
+
+    // paths cannot go through scalars
+    let err = to_vec_with(&value, &Projection::include(&["/kind/x"]).unwrap()).unwrap_err();
+    assert_eq!(err.pointer(), Some("/kind/x"));
+
+    // tokens are unescaped, and `*` is an ordinary key on objects
+    let value = serde_json::json!({"a/b": 1, "*": 2, "c": 3});
+    let projection = Projection::include(&["/a~1b", "/*"]).unwrap();
+    assert_eq!(
+        String::from_utf8(to_vec_with(&value, &projection).unwrap()).unwrap(),
+        r#"{"*":2,"a/b":1}"#
+    );
+
+    for pointer in &["", "a", "/a~2"] {
+        assert_eq!(
+            Projection::include(&[pointer]).unwrap_err().kind(),
+            Some(ErrorKind::InvalidPointer)
+        );
+    }
+}
+
+#[test]
+fn projection_variants() {
+    use crate::{projection::Projection, ser::to_vec_with};
+
+    #[derive(Serialize)]
+    enum Event {
+        Created { id: u32, by: &'static str },
+        Moved(u32, u32),
+        Renamed(&'static str),
+    }
+
+    let events = vec![
+        Event::Created { id: 1, by: "me" },
+        Event::Moved(1, 2),
+        Event::Renamed("x"),
+    ];
+    let projection = Projection::exclude(&["/0/Created/by", "/1/Moved/0"])
+        .unwrap()
+        .optional(&["/2/Renamed"])
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(to_vec_with(&events, &projection).unwrap()).unwrap(),
+        r#"[{"Created":{"id":1}},{"Moved":[2]},{}]"#
+    );
+
+    let projection = Projection::include(&["/*/Created"]).unwrap();
+    let err = to_vec_with(&events, &projection).unwrap_err();
+    assert_eq!(err.pointer(), Some("/1/Created"));
+}
+
+#[cfg(feature = "mac")]
+#[test]
+fn mac_of_canonical_json() {
+    use crate::mac::{self, HmacSha256, HmacSha512};
+
+    #[derive(Serialize)]
+    struct Event {
+        b: Vec<u8>,
+        a: &'static str,
+    }
+
+    // tag computed with Python's hmac over `{"a":"x","b":[1,2]}`
+    let key = b"webhook secret";
+    let tag = mac::mac::<HmacSha256>(
+        key,
+        &Event {
+            b: vec![1, 2],
+            a: "x",
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        crate::hex::encode(&tag),
+        "2cafdc00c1c5c5e39b1c4f9c64a2f2f0698aa2168c441924f898d4411bb6d574"
+    );
+    let value = serde_json::json!({"b": [1, 2], "a": "x"});
+    assert_eq!(mac::mac::<HmacSha256>(key, &value).unwrap(), tag);
+    assert_eq!(mac::mac::<HmacSha512>(key, &value).unwrap().len(), 64);
+
+    mac::verify_mac::<HmacSha256>(key, &value, &tag).unwrap();
+    for (key, value) in &[
+        (&b"another secret"[..], value.clone()),
+        (&key[..], serde_json::json!({"b": [1, 2], "a": "y"})),
+    ] {
+        assert_eq!(
+            mac::verify_mac::<HmacSha256>(key, value, &tag)
+                .unwrap_err()
+                .kind(),
+            Some(ErrorKind::InvalidMac)
+        );
+    }
+    assert!(mac::verify_mac::<HmacSha256>(key, &value, &tag[..16]).is_err());
+}
+
+#[cfg(feature = "mac")]
+#[test]
+fn mac_tokens() {
+    use crate::mac::{self, HmacSha256};
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Cursor {
+        after: u64,
+        order: String,
+    }
+
+    let key = b"webhook secret";
+    let value = serde_json::json!({"b": [1, 2], "a": "x"});
+    let token = mac::encode::<HmacSha256, _>(key, &value).unwrap();
+    assert_eq!(
+        token,
+        "eyJhIjoieCIsImIiOlsxLDJdfQ.LK_cAMHFxeObHE-cZKLy8GmKohaMRBkk-JjUQRu21XQ"
+    );
+    assert_eq!(
+        mac::decode::<HmacSha256, Value>(key, &token).unwrap(),
+        value
+    );
+
+    let cursor = Cursor {
+        after: 42,
+        order: "desc".to_string(),
+    };
+    let token = mac::encode::<HmacSha256, _>(key, &cursor).unwrap();
+    assert_eq!(
+        mac::decode::<HmacSha256, Cursor>(key, &token).unwrap(),
+        cursor
+    );
+
+    // any change to either part is caught
+    let (payload, tag) = token.split_at(token.find('.').unwrap());
+    let mut tampered = payload.as_bytes().to_vec();
+    tampered[3] ^= 1;
+    let tampered = format!("{}{}", String::from_utf8(tampered).unwrap(), tag);
+    assert_eq!(
+        mac::decode::<HmacSha256, Cursor>(key, &tampered)
+            .unwrap_err()
+            .kind(),
+        Some(ErrorKind::InvalidMac)
+    );
+    assert!(mac::decode::<HmacSha256, Cursor>(b"other key", &token).is_err());
+    for malformed in &["", "abc", "a.b.c", "!!.AA"] {
+        assert_eq!(
+            mac::decode::<HmacSha256, Value>(key, malformed)
+                .unwrap_err()
+                .kind(),
+            Some(ErrorKind::InvalidToken),
+            "{}",
+            malformed
+        );
+    }
+
+    // a correctly tagged payload that is not canonical is refused, here
+    // `{"b":[1,2],"a":"x"}` tagged with Python's hmac
+    let unordered = "eyJiIjpbMSwyXSwiYSI6IngifQ.kWNP0lIdDSMyrtkfxTmWwuKQRz58tC06nHVOUMBHRoE";
+    assert_eq!(
+        mac::decode::<HmacSha256, Value>(key, unordered)
+            .unwrap_err()
+            .kind(),
+        Some(ErrorKind::UnorderedKey)
+    );
+}
+
+#[cfg(feature = "mac")]
+#[test]
+fn mac_key_length() {
+    use crate::mac;
+    use hmac::digest::{
+        consts::{U1, U16},
+        crypto_common::KeySizeUser,
+        FixedOutput, Key, KeyInit, MacMarker, Output, OutputSizeUser, Update,
+    };
+
+    // a MAC that only takes 16-byte keys, unlike HMAC
+    struct Fixed;
+    impl KeySizeUser for Fixed {
+        type KeySize = U16;
+    }
+    impl KeyInit for Fixed {
+        fn new(_: &Key<Self>) -> Self {
+            Fixed
+        }
+    }
+    impl OutputSizeUser for Fixed {
+        type OutputSize = U1;
+    }
+    impl Update for Fixed {
+        fn update(&mut self, _: &[u8]) {}
+    }
+    impl FixedOutput for Fixed {
+        fn finalize_into(self, _: &mut Output<Self>) {}
+    }
+    impl MacMarker for Fixed {}
+
+    assert!(mac::mac::<Fixed>(&[0; 16], &1).is_ok());
+    for key in &[&[0; 15][..], &[0; 17][..]] {
+        assert_eq!(
+            mac::mac::<Fixed>(key, &1).unwrap_err().kind(),
+            Some(ErrorKind::InvalidKey)
+        );
+        assert_eq!(
+            mac::encode::<Fixed, _>(key, &1).unwrap_err().kind(),
+            Some(ErrorKind::InvalidKey)
+        );
+    }
+}
+
+#[test]
+fn canonical_value_parse_and_print() {
+    use crate::canonical_value::{to_value, CanonicalValue};
+    use std::convert::TryFrom;
+
+    let value: CanonicalValue = "{ \"b\": [1, -2, null], \"a\": {\"é\": true} }"
+        .parse()
+        .unwrap();
+    assert_eq!(value.to_string(), r#"{"a":{"é":true},"b":[1,-2,null]}"#);
+    let back: CanonicalValue = de::from_str(&value.to_string()).unwrap();
+    assert_eq!(back.to_string(), value.to_string());
+    assert_eq!(
+        value.into_inner(),
+        serde_json::json!({"a": {"é": true}, "b": [1, -2, null]})
+    );
+
+    // integral floats become integers on the way in
+    assert_eq!(to_value(1.0f64).unwrap().into_inner(), serde_json::json!(1));
+    assert!(to_value(0.5f64).is_err());
+
+    for input in &["1.5", "[1.0]", "{\"a\":1e3}", "{\"a\":1,\"a\":2}"] {
+        assert!(input.parse::<CanonicalValue>().is_err(), "{}", input);
+        assert!(de::from_str::<CanonicalValue>(input).is_err(), "{}", input);
+    }
+    // u128 values beyond u64 cannot be held
+    assert!(de::from_str::<CanonicalValue>("340282366920938463463374607431768211455").is_err());
+
+    let err = CanonicalValue::try_from(serde_json::json!({"a": [0, 1.5]})).unwrap_err();
+    assert_eq!(err.kind(), Some(ErrorKind::Float));
+    assert_eq!(err.pointer(), Some("/a/1"));
+    let value = CanonicalValue::try_from(serde_json::json!({"a": [0, u64::MAX]})).unwrap();
+    assert_eq!(value.to_string(), r#"{"a":[0,18446744073709551615]}"#);
+}
+
+#[test]
+fn canonical_value_equality_and_order() {
+    use crate::canonical_value::{to_value, CanonicalValue};
+    use std::collections::{BTreeSet, HashSet};
+
+    #[derive(Serialize)]
+    struct Ab {
+        b: u8,
+        a: u8,
+    }
+
+    let parsed: CanonicalValue = r#"{"a":1,"b":2}"#.parse().unwrap();
+    let built = to_value(Ab { b: 2, a: 1 }).unwrap();
+    let from_map = to_value(treemap!("b" => 2, "a" => 1)).unwrap();
+    assert_eq!(parsed, built);
+    assert_eq!(parsed, from_map);
+    assert_eq!(parsed.as_bytes(), br#"{"a":1,"b":2}"#);
+    assert_ne!(parsed, "{\"a\":1,\"b\":3}".parse().unwrap());
+
+    let set: HashSet<_> = vec![parsed.clone(), built, from_map].into_iter().collect();
+    assert_eq!(set.len(), 1);
+
+    // ordered by canonical bytes
+    let ordered: BTreeSet<CanonicalValue> = ["9", "10", "\"a\"", "[]", "{}", "null", "-1"]
+        .iter()
+        .map(|s| s.parse().unwrap())
+        .collect();
+    let ordered: Vec<String> = ordered.iter().map(ToString::to_string).collect();
+    assert_eq!(ordered, ["\"a\"", "-1", "10", "9", "[]", "null", "{}"]);
+}
+
+#[test]
+fn canonical_value_pointer_editing() {
+    use crate::canonical_value::CanonicalValue;
+
+    let mut doc: CanonicalValue = r#"{"a":{"b":[1,2,3]},"c":"x"}"#.parse().unwrap();
+    assert_eq!(doc.pointer("/a/b/1"), Some(&serde_json::json!(2)));
+    assert_eq!(doc["c"], "x");
+    assert_eq!(doc["a"]["b"][2], 3);
+    assert_eq!(doc["missing"], Value::Null);
+    assert_eq!(doc.get::<Vec<u8>>("/a/b").unwrap(), [1, 2, 3]);
+    assert_eq!(doc.get::<String>("/c").unwrap(), "x");
+    assert_eq!(
+        doc.get::<u8>("/z").unwrap_err().kind(),
+        Some(ErrorKind::MissingPath)
+    );
+
+    // members stay sorted and the bytes follow every edit
+    assert_eq!(doc.set("/B", "new").unwrap(), None);
+    assert_eq!(doc.set("/c", &1).unwrap(), Some(serde_json::json!("x")));
+    doc.set("/a/b/-", &4).unwrap();
+    assert_eq!(doc.set("/a/b/0", &0).unwrap(), Some(serde_json::json!(1)));
+    assert_eq!(doc.to_string(), r#"{"B":"new","a":{"b":[0,2,3,4]},"c":1}"#);
+    assert_eq!(doc, doc.to_string().parse().unwrap());
+
+    assert_eq!(doc.remove("/a/b/1").unwrap(), 2);
+    assert_eq!(doc.remove("/B").unwrap(), "new");
+    let len = doc
+        .edit("/a/b", |node| {
+            node.as_array_mut().unwrap().push(5.into());
+            node.as_array().unwrap().len()
+        })
+        .unwrap();
+    assert_eq!(len, 4);
+    assert_eq!(doc.as_bytes(), br#"{"a":{"b":[0,3,4,5]},"c":1}"#);
+
+    // failed edits leave the document as it was
+    let before = doc.clone();
+    let err = doc.set("/a/x", &[0.5]).unwrap_err();
+    assert_eq!(err.kind(), Some(ErrorKind::Float));
+    assert_eq!(err.pointer(), Some("/a/x/0"));
+    let err = doc.set("/b", &1.0f64).unwrap_err();
+    assert_eq!(err.kind(), Some(ErrorKind::Float));
+    assert_eq!(err.pointer(), Some("/b"));
+    let err = doc
+        .edit("/a", |node| node["f"] = serde_json::json!(1.5))
+        .unwrap_err();
+    assert_eq!(err.kind(), Some(ErrorKind::Float));
+    assert_eq!(err.pointer(), Some("/a/f"));
+    for pointer in &["/a/b/9", "/a/b/01", "/x/y", "/c/0"] {
+        let err = doc.set(pointer, &0).unwrap_err();
+        assert_eq!(err.kind(), Some(ErrorKind::MissingPath), "{}", pointer);
+        assert_eq!(err.pointer(), Some(*pointer));
+    }
+    assert_eq!(
+        doc.remove("").unwrap_err().kind(),
+        Some(ErrorKind::InvalidPointer)
+    );
+    assert_eq!(
+        doc.set("a", &0).unwrap_err().kind(),
+        Some(ErrorKind::InvalidPointer)
+    );
+    assert_eq!(doc, before);
+
+    assert_eq!(doc.set("", &[1, 2]).unwrap(), Some(before.into_inner()));
+    assert_eq!(doc.to_string(), "[1,2]");
+}
+
+#[test]
+fn canonical_value_failed_deep_edit() {
+    use crate::canonical_value::CanonicalValue;
+    use std::convert::TryFrom;
+
+    fn nested(depth: usize) -> Value {
+        (0..depth).fold(Value::Null, |v, _| Value::Array(vec![v]))
+    }
+
+    // each edit is fine on its own, but the result nests deeper than the
+    // default limit of 128
+    let original = CanonicalValue::try_from(serde_json::json!({"a": nested(100)})).unwrap();
+    let deep = format!("/a{}", "/0".repeat(99));
+    let mut doc = original.clone();
+    assert_eq!(
+        doc.set(&deep, &nested(60)).unwrap_err().kind(),
+        Some(ErrorKind::TooDeep)
+    );
+    assert_eq!(
+        doc.edit(&deep, |node| *node = nested(60))
+            .unwrap_err()
+            .kind(),
+        Some(ErrorKind::TooDeep)
+    );
+    assert_eq!(doc.as_value(), original.as_value());
+    assert_eq!(doc.as_bytes(), original.as_bytes());
+    assert_eq!(doc, original);
+}
+
+#[test]
+fn json_patch_apply() {
+    use crate::canonical_value::CanonicalValue;
+    use crate::patch::Patch;
+
+    fn patched(doc: &str, patch: &str) -> crate::error::Result<String> {
+        let mut doc: CanonicalValue = doc.parse().unwrap();
+        let patch: Patch = serde_json::from_str(patch).unwrap();
+        doc.apply_patch(&patch)?;
+        Ok(doc.to_string())
+    }
+
+    // examples from RFC 6902, appendix A
+    let cases = [
+        (
+            r#"{"foo":"bar"}"#,
+            r#"[{"op":"add","path":"/baz","value":"qux"}]"#,
+            r#"{"baz":"qux","foo":"bar"}"#,
+        ),
+        (
+            r#"{"foo":["bar","baz"]}"#,
+            r#"[{"op":"add","path":"/foo/1","value":"qux"}]"#,
+            r#"{"foo":["bar","qux","baz"]}"#,
+        ),
+        (
+            r#"{"baz":"qux","foo":"bar"}"#,
+            r#"[{"op":"remove","path":"/baz"}]"#,
+            r#"{"foo":"bar"}"#,
+        ),
+        (
+            r#"{"baz":"qux","foo":"bar"}"#,
+            r#"[{"op":"replace","path":"/baz","value":"boo"}]"#,
+            r#"{"baz":"boo","foo":"bar"}"#,
+        ),
+        (
+            r#"{"foo":{"bar":"baz","waldo":"fred"},"qux":{"corge":"grault"}}"#,
+            r#"[{"op":"move","from":"/foo/waldo","path":"/qux/thud"}]"#,
+            r#"{"foo":{"bar":"baz"},"qux":{"corge":"grault","thud":"fred"}}"#,
+        ),
+        (
+            r#"{"foo":["all","grass","cows","eat"]}"#,
+            r#"[{"op":"move","from":"/foo/1","path":"/foo/3"}]"#,
+            r#"{"foo":["all","cows","eat","grass"]}"#,
+        ),
+        (
+            r#"{"baz":"qux","foo":["a",2,"c"]}"#,
+            r#"[{"op":"test","path":"/baz","value":"qux"},{"op":"test","path":"/foo/1","value":2.0}]"#,
+            r#"{"baz":"qux","foo":["a",2,"c"]}"#,
+        ),
+        (
+            r#"{"foo":"bar"}"#,
+            r#"[{"op":"copy","from":"/foo","path":"/a~1b"}]"#,
+            r#"{"a/b":"bar","foo":"bar"}"#,
+        ),
+        (
+            r#"{"foo":["bar"]}"#,
+            r#"[{"op":"add","path":"/foo/-","value":["abc","def"]}]"#,
+            r#"{"foo":["bar",["abc","def"]]}"#,
+        ),
+    ];
+    for &(doc, patch, expected) in &cases {
+        assert_eq!(patched(doc, patch).unwrap(), expected, "{}", patch);
+    }
+
+    let kind = |doc, patch| patched(doc, patch).unwrap_err().kind();
+    assert_eq!(
+        kind(
+            r#"{"baz":"qux"}"#,
+            r#"[{"op":"test","path":"/baz","value":"bar"}]"#
+        ),
+        Some(ErrorKind::TestFailed)
+    );
+    assert_eq!(
+        kind(
+            r#"{"foo":"bar"}"#,
+            r#"[{"op":"add","path":"/baz/bat","value":1}]"#
+        ),
+        Some(ErrorKind::MissingPath)
+    );
+    assert_eq!(
+        kind(
+            r#"{"a":{"b":1}}"#,
+            r#"[{"op":"move","from":"/a","path":"/a/b/c"}]"#
+        ),
+        Some(ErrorKind::InvalidPatch)
+    );
+    assert_eq!(
+        kind(r#"[1]"#, r#"[{"op":"replace","path":"/01","value":2}]"#),
+        Some(ErrorKind::MissingPath)
+    );
+
+    // a patch that adds a float changes nothing
+    let mut doc: CanonicalValue = r#"{"a":1}"#.parse().unwrap();
+    let patch: Patch = serde_json::from_str(
+        r#"[{"op":"add","path":"/b","value":2},{"op":"add","path":"/c","value":[0.5]}]"#,
+    )
+    .unwrap();
+    let err = doc.apply_patch(&patch).unwrap_err();
+    assert_eq!(err.kind(), Some(ErrorKind::Float));
+    assert_eq!(err.pointer(), Some("/c/0"));
+    assert_eq!(doc.to_string(), r#"{"a":1}"#);
+}
+
+#[test]
+fn json_patch_diff() {
+    use crate::canonical_value::CanonicalValue;
+
+    let pairs = [
+        (
+            r#"{"a":1,"b":[1,2,3],"c":{"d":"e"}}"#,
+            r#"{"a":2,"b":[1,3],"c":{"f":null},"g":true}"#,
+        ),
+        (r#"[1,2,3]"#, r#"[0,1,2,3,4]"#),
+        (r#"[1,2,3,4,5]"#, r#"[1,5]"#),
+        (r#"[1,2,3]"#, r#"[0,1,2]"#),
+        (r#"[1,2,3,4]"#, r#"[5,2,6,4,7]"#),
+        (r#"[{"a":1},2,{"a":3}]"#, r#"[0,{"a":1},{"a":4}]"#),
+        (r#"{"a":[{"b":1}]}"#, r#"{"a":[{"b":2}]}"#),
+        (r#"{"a~/b":1}"#, r#"{"a~/b":2}"#),
+        (r#"{"a":1}"#, r#"[1]"#),
+        (r#"null"#, r#"null"#),
+    ];
+    for &(old, new) in &pairs {
+        let old: CanonicalValue = old.parse().unwrap();
+        let new: CanonicalValue = new.parse().unwrap();
+        let patch = old.diff(&new);
+        let mut applied = old.clone();
+        applied.apply_patch(&patch).unwrap();
+        assert_eq!(applied, new, "{:?}", patch);
+    }
+
+    let old: CanonicalValue = r#"{"a":1,"b":[1,2,3,4],"c":{"d":"e"}}"#.parse().unwrap();
+    let new: CanonicalValue = r#"{"a":1,"b":[1,4],"c":{"d":"f"},"e":[]}"#.parse().unwrap();
+    let patch = old.diff(&new);
+    assert_eq!(
+        to_string(&patch).unwrap(),
+        concat!(
+            r#"[{"op":"remove","path":"/b/1"},{"op":"remove","path":"/b/1"},"#,
+            r#"{"op":"replace","path":"/c/d","value":"f"},"#,
+            r#"{"op":"add","path":"/e","value":[]}]"#
+        )
+    );
+    assert!(old.diff(&old).0.is_empty());
+
+    // a shifted array takes one addition and one removal
+    let shifted: CanonicalValue = "[1,2,3]".parse().unwrap();
+    let patch = shifted.diff(&"[0,1,2]".parse().unwrap());
+    assert_eq!(
+        to_string(&patch).unwrap(),
+        r#"[{"op":"add","path":"/0","value":0},{"op":"remove","path":"/3"}]"#
+    );
+    let changed: CanonicalValue = r#"["a",{"b":1},"c","d"]"#.parse().unwrap();
+    let patch = changed.diff(&r#"["x","a",{"b":2},"c"]"#.parse().unwrap());
+    assert_eq!(
+        to_string(&patch).unwrap(),
+        concat!(
+            r#"[{"op":"add","path":"/0","value":"x"},"#,
+            r#"{"op":"replace","path":"/2/b","value":2},{"op":"remove","path":"/4"}]"#
+        )
+    );
+    assert_eq!(
+        to_string(&new.diff(&old)).unwrap(),
+        concat!(
+            r#"[{"op":"add","path":"/b/1","value":2},{"op":"add","path":"/b/2","value":3},"#,
+            r#"{"op":"replace","path":"/c/d","value":"e"},{"op":"remove","path":"/e"}]"#
+        )
+    );
+}
+
+#[test]
+fn json_merge_patch() {
+    use crate::canonical_value::{merge_patch, merge_patch_diff, CanonicalValue};
+
+    // examples from RFC 7386, appendix A
+    let cases = [
+        (r#"{"a":"b"}"#, r#"{"a":"c"}"#, r#"{"a":"c"}"#),
+        (r#"{"a":"b"}"#, r#"{"b":"c"}"#, r#"{"a":"b","b":"c"}"#),
+        (r#"{"a":"b"}"#, r#"{"a":null}"#, r#"{}"#),
+        (r#"{"a":"b","b":"c"}"#, r#"{"a":null}"#, r#"{"b":"c"}"#),
+        (r#"{"a":["b"]}"#, r#"{"a":"c"}"#, r#"{"a":"c"}"#),
+        (r#"{"a":"c"}"#, r#"{"a":["b"]}"#, r#"{"a":["b"]}"#),
+        (
+            r#"{"a":{"b":"c"}}"#,
+            r#"{"a":{"b":"d","c":null}}"#,
+            r#"{"a":{"b":"d"}}"#,
+        ),
+        (r#"{"a":[{"b":"c"}]}"#, r#"{"a":[1]}"#, r#"{"a":[1]}"#),
+        (r#"["a","b"]"#, r#"["c","d"]"#, r#"["c","d"]"#),
+        (r#"{"a":"b"}"#, r#"["c"]"#, r#"["c"]"#),
+        (r#"{"a":"foo"}"#, r#"null"#, r#"null"#),
+        (r#"{"a":"foo"}"#, r#""bar""#, r#""bar""#),
+        (r#"{"e":null}"#, r#"{"a":1}"#, r#"{"a":1,"e":null}"#),
+        (r#"[1,2]"#, r#"{"a":"b","c":null}"#, r#"{"a":"b"}"#),
+        (
+            r#"{}"#,
+            r#"{"a":{"bb":{"ccc":null}}}"#,
+            r#"{"a":{"bb":{}}}"#,
+        ),
+    ];
+    for &(target, patch, expected) in &cases {
+        let mut target: CanonicalValue = target.parse().unwrap();
+        merge_patch(&mut target, &patch.parse().unwrap()).unwrap();
+        assert_eq!(target.to_string(), expected, "{}", patch);
+        assert_eq!(target, expected.parse().unwrap());
+    }
+
+    let old: CanonicalValue = r#"{"a":{"b":1,"c":[1]},"d":"e","f":null}"#.parse().unwrap();
+    let new: CanonicalValue = r#"{"a":{"b":1,"c":[2]},"f":null,"g":{"h":{}}}"#.parse().unwrap();
+    let patch = merge_patch_diff(&old, &new).unwrap();
+    assert_eq!(
+        patch.to_string(),
+        r#"{"a":{"c":[2]},"d":null,"g":{"h":{}}}"#
+    );
+    let mut merged = old.clone();
+    merge_patch(&mut merged, &patch).unwrap();
+    assert_eq!(merged, new);
+    assert_eq!(merge_patch_diff(&old, &old).unwrap().to_string(), "{}");
+    assert_eq!(
+        merge_patch_diff(&old, &"[null]".parse().unwrap())
+            .unwrap()
+            .to_string(),
+        "[null]"
+    );
+
+    // a null member cannot be added by a merge patch
+    for new in &[r#"{"a":null}"#, r#"{"a":{"b":{"c":null}}}"#] {
+        let err =
+            merge_patch_diff(&"{\"a\":1}".parse().unwrap(), &new.parse().unwrap()).unwrap_err();
+        assert_eq!(err.kind(), Some(ErrorKind::MergePatchNull), "{}", new);
+    }
+    let err = merge_patch_diff(&old, &r#"{"x":{"y":null}}"#.parse().unwrap()).unwrap_err();
+    assert_eq!(err.pointer(), Some("/x/y"));
+}
+
+#[test]
+fn structural_diff() {
+    use crate::diff::{diff, equal, Change};
+
+    #[derive(Serialize)]
+    struct Doc {
+        b: Vec<u8>,
+        a: &'static str,
+    }
+
+    let signed: Value = serde_json::from_str(r#"{"a":"x","b":[1,2,3],"c":{"d":1}}"#).unwrap();
+    let ours: Value = serde_json::from_str(r#"{"c":{"d":2,"e":[]},"b":[1,3],"a":"x"}"#).unwrap();
+    let result = diff(&signed, &ours).unwrap();
+    assert_eq!(
+        result.changes,
+        vec![
+            Change::Removed {
+                pointer: "/b/1".into(),
+                value: 2.into(),
+            },
+            Change::Changed {
+                pointer: "/c/d".into(),
+                before: 1.into(),
+                after: 2.into(),
+            },
+            Change::Added {
+                pointer: "/c/e".into(),
+                value: serde_json::json!([]),
+            },
+        ]
+    );
+    // {"a":"x","b":[1,2 against {"a":"x","b":[1,3
+    assert_eq!(result.first_difference, Some(16));
+    assert!(!result.is_equal());
+    assert_eq!(
+        result.to_string(),
+        "- /b/1: 2\n~ /c/d: 1 -> 2\n+ /c/e: []\ncanonical forms first differ at byte 16"
+    );
+
+    // field order and integral floats do not matter
+    let doc = Doc { b: vec![1], a: "x" };
+    let parsed: Value = serde_json::from_str(r#"{"a":"x","b":[1.0]}"#).unwrap();
+    assert!(equal(&doc, &parsed).unwrap());
+    let same = diff(&doc, &parsed).unwrap();
+    assert!(same.is_equal() && same.changes.is_empty());
+    assert_eq!(same.to_string(), "canonical forms are equal");
+    assert!(!equal(&doc, &serde_json::json!({"a": "x"})).unwrap());
+
+    // a prefix differs where the shorter one ends
+    let prefix = diff(&serde_json::json!("ab"), &serde_json::json!(["ab"])).unwrap();
+    assert_eq!(prefix.first_difference, Some(0));
+    assert_eq!(
+        prefix.to_string().lines().next(),
+        Some(r#"~ (root): "ab" -> ["ab"]"#)
+    );
+    let longer = diff(&serde_json::json!([1]), &serde_json::json!([1, 2])).unwrap();
+    assert_eq!(longer.first_difference, Some(2));
+
+    // removed values are looked up after the changes before them
+    let shifted = diff(&[1, 2, 3], &[0, 1, 2]).unwrap();
+    assert_eq!(
+        shifted.to_string().lines().collect::<Vec<_>>()[..2],
+        ["+ /0: 0", "- /3: 3"]
+    );
+
+    assert!(diff(&0.5, &1).is_err());
+}
+
+#[test]
+fn canonical_value_error_kinds() {
+    use crate::canonical_value::{to_value, CanonicalValue};
+
+    let err = "1.5".parse::<CanonicalValue>().unwrap_err();
+    assert_eq!(err.kind(), Some(ErrorKind::Float));
+    assert_eq!(err.to_string(), "error: floating point number: 1.5");
+
+    let err = r#"[0,{"a":[1e3]}]"#.parse::<CanonicalValue>().unwrap_err();
+    assert_eq!(err.kind(), Some(ErrorKind::Float));
+    assert_eq!(err.pointer(), Some("/1/a/0"));
+    let err = r#"{"a":{"b":1,"b":2}}"#.parse::<CanonicalValue>().unwrap_err();
+    assert_eq!(err.kind(), Some(ErrorKind::DuplicateKey));
+    assert_eq!(err.pointer(), Some("/a"));
+    assert_eq!(err.to_string(), "error: repeated key: b at \"/a\"");
+
+    #[derive(Serialize)]
+    struct Wide {
+        n: u128,
+    }
+    let err = to_value(Wide { n: u128::MAX }).unwrap_err();
+    assert_eq!(err.kind(), Some(ErrorKind::NumberOutOfRange));
+    assert_eq!(err.pointer(), Some("/n"));
+    assert!(!err.to_string().contains("error: error:"), "{}", err);
+
+    // other deserializers only carry the message, but without a second prefix
+    let err =
+        de::from_str::<CanonicalValue>("[1,340282366920938463463374607431768211455]").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "error: number out of range: 340282366920938463463374607431768211455"
+    );
+}
@@ -0,0 +1,182 @@
+//! The Update Framework (TUF) `signed`/`signatures` envelope.
+//!
+//! TUF metadata is a JSON object whose `signed` member holds the payload and
+//! whose `signatures` member lists signatures over the canonical form of that
+//! payload, each as a `{"keyid":…,"sig":…}` object naming the key that made
+//! it. Key IDs are the hex SHA-256 of the canonical form of the public key.
+//! Signing and verification go through the `Signer` trait and
+//! `PublicKey::verify`; Ed25519 support comes with the `ed25519` feature.
+
+use super::error::{Error, ErrorKind, Result};
+use super::hash::{to_digest, Sha256};
+use super::{hex, ser};
+use serde::Serialize;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A payload together with the signatures made over its canonical form.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Signed<T> {
+    pub signatures: Vec<Signature>,
+    pub signed: T,
+}
+
+/// One entry of `signatures`.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Signature {
+    pub keyid: String,
+    /// Hex-encoded signature.
+    pub sig: String,
+}
+
+/// A public key in TUF metadata. Members other than `keytype`, `scheme` and
+/// `keyval` are kept as they are, since they take part in the key ID.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PublicKey {
+    pub keytype: String,
+    pub scheme: String,
+    pub keyval: KeyValue,
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, Value>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct KeyValue {
+    pub public: String,
+}
+
+/// Something that can sign on behalf of a TUF key.
+pub trait Signer {
+    fn public_key(&self) -> PublicKey;
+
+    fn sign(&self, message: &[u8]) -> Vec<u8>;
+}
+
+impl PublicKey {
+    /// The hex SHA-256 of the canonical form of the key.
+    pub fn key_id(&self) -> Result<String> {
+        Ok(hex::encode(&to_digest::<Sha256, _>(self)?))
+    }
+
+    /// Checks `signature` over `message`. Key types this build does not
+    /// support never verify.
+    #[cfg_attr(not(feature = "ed25519"), allow(unused_variables))]
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        match (self.keytype.as_str(), self.scheme.as_str()) {
+            #[cfg(feature = "ed25519")]
+            ("ed25519", "ed25519") => ed25519::verify(self, message, signature),
+            _ => false,
+        }
+    }
+}
+
+impl<T> Signed<T>
+where
+    T: Serialize,
+{
+    /// An envelope around `signed` with no signatures yet.
+    pub fn new(signed: T) -> Self {
+        Signed {
+            signatures: Vec::new(),
+            signed,
+        }
+    }
+
+    /// Signs the canonical form of `signed`, replacing any signature made
+    /// earlier with the same key.
+    pub fn sign<S>(&mut self, signer: &S) -> Result<()>
+    where
+        S: Signer + ?Sized,
+    {
+        let keyid = signer.public_key().key_id()?;
+        let sig = hex::encode(&signer.sign(&ser::to_vec(&self.signed)?));
+        match self.signatures.iter_mut().find(|s| s.keyid == keyid) {
+            Some(existing) => existing.sig = sig,
+            None => self.signatures.push(Signature { keyid, sig }),
+        }
+        Ok(())
+    }
+
+    /// Checks that at least `threshold` of the trusted `keys`, given by key
+    /// ID as a TUF role lists them, have a valid signature over the
+    /// canonical form of `signed`. Signatures by other keys are ignored and
+    /// each key counts once. A key listed under an ID other than its own
+    /// never counts, so one key under two IDs cannot meet a threshold of
+    /// two. A threshold of zero is never met.
+    pub fn verify<'a, I>(&self, keys: I, threshold: usize) -> Result<()>
+    where
+        I: IntoIterator<Item = (&'a String, &'a PublicKey)>,
+    {
+        let keys: BTreeMap<_, _> = keys.into_iter().collect();
+        let message = ser::to_vec(&self.signed)?;
+
+        let mut valid = BTreeSet::new();
+        for signature in &self.signatures {
+            let key = match keys.get(&signature.keyid) {
+                Some(key) => key,
+                None => continue,
+            };
+            if key.key_id()? != signature.keyid {
+                continue;
+            }
+            let sig = match hex::decode(&signature.sig) {
+                Ok(sig) => sig,
+                Err(_) => continue,
+            };
+            if key.verify(&message, &sig) {
+                valid.insert(&signature.keyid);
+            }
+        }
+
+        if threshold == 0 || valid.len() < threshold {
+            return Err(Error::Verify(ErrorKind::SignatureThreshold));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "ed25519")]
+mod ed25519 {
+    use super::{hex, KeyValue, PublicKey, Signer};
+    use ed25519_dalek::{Signature, SigningKey, Verifier, VerifyingKey};
+    use std::{collections::BTreeMap, convert::TryFrom};
+
+    impl From<&VerifyingKey> for PublicKey {
+        fn from(key: &VerifyingKey) -> Self {
+            PublicKey {
+                keytype: "ed25519".to_string(),
+                scheme: "ed25519".to_string(),
+                keyval: KeyValue {
+                    public: hex::encode(key.as_bytes()),
+                },
+                extra: BTreeMap::new(),
+            }
+        }
+    }
+
+    impl Signer for SigningKey {
+        fn public_key(&self) -> PublicKey {
+            PublicKey::from(&self.verifying_key())
+        }
+
+        fn sign(&self, message: &[u8]) -> Vec<u8> {
+            ed25519_dalek::Signer::sign(self, message).to_vec()
+        }
+    }
+
+    pub(super) fn verify(key: &PublicKey, message: &[u8], signature: &[u8]) -> bool {
+        let key = match hex::decode(&key.keyval.public)
+            .ok()
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes.as_slice()).ok())
+            .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+        {
+            Some(key) => key,
+            None => return false,
+        };
+        match Signature::from_slice(signature) {
+            Ok(signature) => key.verify(message, &signature).is_ok(),
+            Err(_) => false,
+        }
+    }
+}
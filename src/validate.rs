@@ -0,0 +1,482 @@
+//! Checks whether a document is already canonical without deserializing it.
+//!
+//! `validate` scans the raw bytes once and keeps going after recoverable
+//! problems, so a single call reports every place where the document departs
+//! from canonical form. Only malformed JSON stops the scan. The kinds reported
+//! are the same `ErrorKind`s the parser and the serializer use.
+//!
+//! Both functions take the same `Limits` as the parser, the default ones
+//! unless a `_with_limits` variant is used. Nesting deeper than the limit
+//! stops the scan with a `TooDeep` violation; long strings and large objects
+//! are reported and the scan goes on.
+
+use super::de;
+use super::error::ErrorKind;
+use super::limits::Limits;
+use std::{borrow::Cow, char, fmt, str};
+
+/// A place where a document departs from canonical form.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Violation {
+    pub kind: ErrorKind,
+    /// Byte offset into the input.
+    pub offset: usize,
+    /// 1-based line of `offset`.
+    pub line: usize,
+    /// 1-based column of `offset`, counted in bytes.
+    pub column: usize,
+    /// JSON Pointer (RFC 6901) to the node the violation belongs to.
+    pub pointer: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            fmt,
+            "{}:{}: {} at \"{}\"",
+            self.line, self.column, self.kind, self.pointer
+        )
+    }
+}
+
+/// Returns `true` if `input` is byte-for-byte canonical JSON.
+pub fn is_canonical(input: &[u8]) -> bool {
+    is_canonical_with_limits(input, Limits::default())
+}
+
+/// `is_canonical` that also requires `input` to stay within `limits`.
+pub fn is_canonical_with_limits(input: &[u8], limits: Limits) -> bool {
+    de::from_slice_with_limits::<serde::de::IgnoredAny>(input, limits).is_ok()
+}
+
+/// Returns every violation of the canonical form in `input`, ordered by
+/// offset. An empty list means the input is canonical.
+pub fn validate(input: &[u8]) -> Vec<Violation> {
+    validate_with_limits(input, Limits::default())
+}
+
+/// `validate` that also reports where `input` goes over `limits`.
+pub fn validate_with_limits(input: &[u8], limits: Limits) -> Vec<Violation> {
+    let mut validator = Validator {
+        input,
+        index: 0,
+        path: Vec::new(),
+        violations: Vec::new(),
+        limits,
+    };
+
+    if validator.value().is_ok() {
+        validator.skip_whitespace();
+        if validator.index < input.len() {
+            validator.report(ErrorKind::TrailingCharacters, validator.index);
+        }
+    }
+
+    let mut violations = validator.violations;
+    locate(input, &mut violations);
+    violations
+}
+
+// Escapes a reference token as described in RFC 6901, section 3.
+pub(crate) fn escape_token(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+// Splits a JSON Pointer into its unescaped reference tokens, or `None` when
+// it is not a valid pointer.
+pub(crate) fn parse_pointer(pointer: &str) -> Option<Vec<String>> {
+    if pointer.is_empty() {
+        return Some(Vec::new());
+    }
+    let rest = pointer.strip_prefix('/')?;
+    rest.split('/')
+        .map(|token| {
+            let mut out = String::with_capacity(token.len());
+            let mut chars = token.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '~' => match chars.next() {
+                        Some('0') => out.push('~'),
+                        Some('1') => out.push('/'),
+                        _ => return None,
+                    },
+                    c => out.push(c),
+                }
+            }
+            Some(out)
+        })
+        .collect()
+}
+
+// Fills in line and column numbers in a single pass over the input.
+fn locate(input: &[u8], violations: &mut [Violation]) {
+    violations.sort_by_key(|v| v.offset);
+
+    let mut line = 1;
+    let mut line_start = 0;
+    let mut pos = 0;
+    for violation in violations.iter_mut() {
+        while pos < violation.offset && pos < input.len() {
+            if input[pos] == b'\n' {
+                line += 1;
+                line_start = pos + 1;
+            }
+            pos += 1;
+        }
+        violation.line = line;
+        violation.column = violation.offset - line_start + 1;
+    }
+}
+
+// Malformed JSON the scan cannot recover from. The violation describing it
+// has already been recorded.
+struct Fatal;
+
+type Scan<T> = Result<T, Fatal>;
+
+struct Validator<'a> {
+    input: &'a [u8],
+    index: usize,
+    // one token per array or object the scan is in
+    path: Vec<String>,
+    violations: Vec<Violation>,
+    limits: Limits,
+}
+
+impl<'a> Validator<'a> {
+    fn report(&mut self, kind: ErrorKind, offset: usize) {
+        let pointer = self.path.iter().fold(String::new(), |mut pointer, token| {
+            pointer.push('/');
+            pointer.push_str(token);
+            pointer
+        });
+        self.violations.push(Violation {
+            kind,
+            offset,
+            line: 0,
+            column: 0,
+            pointer,
+        });
+    }
+
+    fn fatal<T>(&mut self, kind: ErrorKind, offset: usize) -> Scan<T> {
+        self.report(kind, offset);
+        Err(Fatal)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.index).cloned()
+    }
+
+    fn skip_whitespace(&mut self) {
+        let start = self.index;
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.peek() {
+            self.index += 1;
+        }
+        if self.index > start {
+            self.report(ErrorKind::Whitespace, start);
+        }
+    }
+
+    // Consumes `byte` after any whitespace, or fails.
+    fn expect(&mut self, byte: u8) -> Scan<()> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b) if b == byte => {
+                self.index += 1;
+                Ok(())
+            }
+            None => self.fatal(ErrorKind::UnexpectedEof, self.index),
+            Some(_) => self.fatal(ErrorKind::UnexpectedByte, self.index),
+        }
+    }
+
+    fn value(&mut self) -> Scan<()> {
+        self.skip_whitespace();
+        match self.peek() {
+            None => self.fatal(ErrorKind::UnexpectedEof, self.index),
+            Some(b'n') => self.ident(b"null"),
+            Some(b't') => self.ident(b"true"),
+            Some(b'f') => self.ident(b"false"),
+            Some(b'"') => self.limited_string().map(|_| ()),
+            Some(b'[') | Some(b'{') if self.too_deep() => {
+                self.fatal(ErrorKind::TooDeep, self.index)
+            }
+            Some(b'[') => self.array(),
+            Some(b'{') => self.object(),
+            Some(b'-') | Some(b'0'..=b'9') => self.number(),
+            Some(_) => self.fatal(ErrorKind::UnexpectedByte, self.index),
+        }
+    }
+
+    // Whether opening an array or object here goes over the depth limit.
+    fn too_deep(&self) -> bool {
+        match self.limits.max_depth {
+            Some(max) => self.path.len() >= max,
+            None => false,
+        }
+    }
+
+    fn limited_string(&mut self) -> Scan<Cow<'a, [u8]>> {
+        let offset = self.index;
+        let string = self.string()?;
+        if let Some(max) = self.limits.max_string_len {
+            if string.len() > max {
+                self.report(ErrorKind::StringTooLong, offset);
+            }
+        }
+        Ok(string)
+    }
+
+    fn ident(&mut self, ident: &[u8]) -> Scan<()> {
+        for &expected in ident {
+            match self.peek() {
+                None => return self.fatal(ErrorKind::UnexpectedEof, self.index),
+                Some(b) if b == expected => self.index += 1,
+                Some(_) => return self.fatal(ErrorKind::UnexpectedByte, self.index),
+            }
+        }
+        Ok(())
+    }
+
+    fn array(&mut self) -> Scan<()> {
+        self.index += 1;
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.index += 1;
+            return Ok(());
+        }
+
+        let mut i = 0usize;
+        loop {
+            self.path.push(i.to_string());
+            let result = self.value();
+            self.path.pop();
+            result?;
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.index += 1,
+                Some(b']') => {
+                    self.index += 1;
+                    return Ok(());
+                }
+                None => return self.fatal(ErrorKind::UnexpectedEof, self.index),
+                Some(_) => return self.fatal(ErrorKind::UnexpectedByte, self.index),
+            }
+            i += 1;
+        }
+    }
+
+    fn object(&mut self) -> Scan<()> {
+        self.index += 1;
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.index += 1;
+            return Ok(());
+        }
+
+        let mut prev_key: Option<Cow<'a, [u8]>> = None;
+        let mut members = 0usize;
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b'"') => {}
+                None => return self.fatal(ErrorKind::UnexpectedEof, self.index),
+                Some(_) => return self.fatal(ErrorKind::UnexpectedByte, self.index),
+            }
+
+            let offset = self.index;
+            let key = self.limited_string()?;
+            members += 1;
+            if self.limits.max_keys == Some(members - 1) {
+                self.report(ErrorKind::TooManyKeys, offset);
+            }
+            self.path.push(escape_token(&String::from_utf8_lossy(&key)));
+
+            if let Some(ref prev) = prev_key {
+                if key < *prev {
+                    self.report(ErrorKind::UnorderedKey, offset);
+                } else if key == *prev {
+                    self.report(ErrorKind::DuplicateKey, offset);
+                }
+            }
+
+            let result = self.expect(b':').and_then(|_| self.value());
+            self.path.pop();
+            result?;
+            prev_key = Some(key);
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.index += 1,
+                Some(b'}') => {
+                    self.index += 1;
+                    return Ok(());
+                }
+                None => return self.fatal(ErrorKind::UnexpectedEof, self.index),
+                Some(_) => return self.fatal(ErrorKind::UnexpectedByte, self.index),
+            }
+        }
+    }
+
+    // Scans a string starting at its opening quote and returns its decoded
+    // bytes, so that keys written with different escapes still compare by
+    // their value.
+    fn string(&mut self) -> Scan<Cow<'a, [u8]>> {
+        self.index += 1;
+        let start = self.index;
+        let mut scratch: Option<Vec<u8>> = None;
+        let mut run = start;
+
+        loop {
+            match self.peek() {
+                None => return self.fatal(ErrorKind::UnexpectedEof, self.index),
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    let escape = self.index;
+                    let decoded = match self.input.get(escape + 1) {
+                        None => return self.fatal(ErrorKind::UnexpectedEof, self.input.len()),
+                        Some(&b) if b == b'"' || b == b'\\' => {
+                            self.index += 2;
+                            b as char
+                        }
+                        Some(&b) => {
+                            let decoded = match b {
+                                b'/' => '/',
+                                b'b' => '\x08',
+                                b'f' => '\x0c',
+                                b'n' => '\n',
+                                b'r' => '\r',
+                                b't' => '\t',
+                                b'u' => match self.unicode_escape() {
+                                    Some(c) => c,
+                                    None => return self.fatal(ErrorKind::InvalidEscape, escape),
+                                },
+                                _ => return self.fatal(ErrorKind::InvalidEscape, escape),
+                            };
+                            if b != b'u' {
+                                self.index += 2;
+                            }
+                            self.report(ErrorKind::NonMinimalEscape, escape);
+                            decoded
+                        }
+                    };
+
+                    let scratch = scratch.get_or_insert_with(Vec::new);
+                    scratch.extend_from_slice(&self.input[run..escape]);
+                    let mut buf = [0; 4];
+                    scratch.extend_from_slice(decoded.encode_utf8(&mut buf).as_bytes());
+                    run = self.index;
+                }
+                Some(_) => self.index += 1,
+            }
+        }
+
+        let end = self.index;
+        self.index += 1;
+
+        if let Err(err) = str::from_utf8(&self.input[start..end]) {
+            self.report(ErrorKind::InvalidUtf8, start + err.valid_up_to());
+        }
+
+        Ok(match scratch {
+            None => Cow::Borrowed(&self.input[start..end]),
+            Some(mut scratch) => {
+                scratch.extend_from_slice(&self.input[run..end]);
+                Cow::Owned(scratch)
+            }
+        })
+    }
+
+    // Decodes a `\uXXXX` escape, or a surrogate pair of them, starting at the
+    // backslash. Lone surrogates decode to U+FFFD.
+    fn unicode_escape(&mut self) -> Option<char> {
+        let input = self.input;
+        let hex = |at: usize| -> Option<u32> {
+            let digits = input.get(at..at + 4)?;
+            u32::from_str_radix(str::from_utf8(digits).ok()?, 16).ok()
+        };
+
+        let first = hex(self.index + 2)?;
+        self.index += 6;
+        if (0xD800..0xDC00).contains(&first) && input[self.index..].starts_with(b"\\u") {
+            if let Some(second) = hex(self.index + 2) {
+                if (0xDC00..0xE000).contains(&second) {
+                    self.index += 6;
+                    let c = 0x10000 + ((first - 0xD800) << 10) + (second - 0xDC00);
+                    return char::from_u32(c);
+                }
+            }
+        }
+        Some(char::from_u32(first).unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+
+    fn number(&mut self) -> Scan<()> {
+        let start = self.index;
+        if self.peek() == Some(b'-') {
+            self.index += 1;
+        }
+
+        let mut leading_zero = false;
+        match self.peek() {
+            Some(b'0') => {
+                self.index += 1;
+                if let Some(b'0'..=b'9') = self.peek() {
+                    leading_zero = true;
+                    self.report(ErrorKind::LeadingZero, start);
+                }
+            }
+            Some(b'1'..=b'9') => {}
+            None => return self.fatal(ErrorKind::UnexpectedEof, self.index),
+            Some(_) => return self.fatal(ErrorKind::UnexpectedByte, self.index),
+        }
+        self.digits();
+
+        let int_end = self.index;
+        let mut float = false;
+        if self.peek() == Some(b'.') {
+            self.index += 1;
+            self.required_digits()?;
+            float = true;
+        }
+        if let Some(b'e') | Some(b'E') = self.peek() {
+            self.index += 1;
+            if let Some(b'+') | Some(b'-') = self.peek() {
+                self.index += 1;
+            }
+            self.required_digits()?;
+            float = true;
+        }
+
+        if float {
+            self.report(ErrorKind::Float, start);
+        } else if !leading_zero {
+            // only ASCII digits and an optional sign were consumed
+            let text = unsafe { str::from_utf8_unchecked(&self.input[start..int_end]) };
+            if text == "-0" {
+                self.report(ErrorKind::NegativeZero, start);
+            } else if text.parse::<i128>().is_err() && text.parse::<u128>().is_err() {
+                self.report(ErrorKind::NumberOutOfRange, start);
+            }
+        }
+        Ok(())
+    }
+
+    fn digits(&mut self) {
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.index += 1;
+        }
+    }
+
+    fn required_digits(&mut self) -> Scan<()> {
+        match self.peek() {
+            Some(b'0'..=b'9') => {
+                self.digits();
+                Ok(())
+            }
+            None => self.fatal(ErrorKind::UnexpectedEof, self.index),
+            Some(_) => self.fatal(ErrorKind::UnexpectedByte, self.index),
+        }
+    }
+}